@@ -0,0 +1,156 @@
+use crate::savestate::{Snapshot, StateStream};
+/*
+$4010 IL-- RRRR  irq enable, loop, rate index
+$4011 -DDD DDDD  direct load of the output level
+$4012 AAAA AAAA  sample address = $C000 + A * 64
+$4013 LLLL LLLL  sample length = L * 16 + 1 bytes
+Samples are 1-bit deltas fetched from cpu memory by dma, see Bus::tick.
+*/
+//ntsc rates in cpu cycles
+const DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+pub struct DMC {
+    pub irq_enabled: bool,
+    pub irq_flag: bool,
+    pub looping: bool,
+    pub timer: u16,
+    pub timer_period: u16,
+    pub output_level: u8,
+    pub sample_address: u16,
+    pub sample_length: u16,
+    pub current_address: u16,
+    pub bytes_remaining: u16,
+    pub sample_buffer: Option<u8>,
+    pub shift: u8,
+    pub bits_remaining: u8,
+    pub silence: bool,
+}
+impl Default for DMC {
+    fn default() -> Self {
+        DMC::new()
+    }
+}
+impl DMC {
+    pub fn new() -> Self {
+        DMC {
+            irq_enabled: false,
+            irq_flag: false,
+            looping: false,
+            timer: 0,
+            timer_period: DMC_RATES[0],
+            output_level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+    pub fn write_control(&mut self, data: u8) {
+        self.irq_enabled = data & 0b1000_0000 != 0;
+        if !self.irq_enabled {
+            self.irq_flag = false;
+        }
+        self.looping = data & 0b0100_0000 != 0;
+        self.timer_period = DMC_RATES[(data & 0b1111) as usize];
+    }
+    pub fn write_output(&mut self, data: u8) {
+        self.output_level = data & 0b0111_1111;
+    }
+    pub fn write_address(&mut self, data: u8) {
+        self.sample_address = 0xc000 | ((data as u16) << 6);
+    }
+    pub fn write_length(&mut self, data: u8) {
+        self.sample_length = ((data as u16) << 4) | 1;
+    }
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+    //address the memory reader wants fetched, if the sample buffer needs refilling
+    pub fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+    pub fn dma_fill(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        self.current_address = if self.current_address == 0xffff {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+    //clocked every cpu cycle, the rate table is already in cpu cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+        if !self.silence {
+            if self.shift & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift = self.shift >> 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift = data;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+impl Snapshot for DMC {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bool(&mut self.irq_enabled);
+        s.bool(&mut self.irq_flag);
+        s.bool(&mut self.looping);
+        s.u16(&mut self.timer);
+        s.u16_of(&mut self.timer_period, &DMC_RATES);
+        s.u8_below(&mut self.output_level, 128);
+        s.u16(&mut self.sample_address);
+        s.u16(&mut self.sample_length);
+        s.u16(&mut self.current_address);
+        s.u16(&mut self.bytes_remaining);
+        s.option_u8(&mut self.sample_buffer);
+        s.u8(&mut self.shift);
+        s.u8_range(&mut self.bits_remaining, 1..=8);
+        s.bool(&mut self.silence);
+    }
+}
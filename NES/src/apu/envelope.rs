@@ -0,0 +1,72 @@
+use crate::savestate::{Snapshot, StateStream};
+/*
+Volume envelope shared by the pulse and noise channels.
+--LC VVVV
+  || ++++- Volume when C is set, otherwise the envelope divider period
+  |+------ Constant volume flag (0: use envelope, 1: use constant volume)
+  +------- Envelope loop flag (also the length counter halt flag)
+*/
+pub struct Envelope {
+    pub start: bool,
+    pub looping: bool,
+    pub constant: bool,
+    pub volume: u8,
+    pub divider: u8,
+    pub decay: u8,
+}
+impl Default for Envelope {
+    fn default() -> Self {
+        Envelope::new()
+    }
+}
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            start: false,
+            looping: false,
+            constant: false,
+            volume: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant = data & 0b0001_0000 != 0;
+        self.volume = data & 0b0000_1111;
+    }
+    //clocked by the frame counter every quarter frame
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+impl Snapshot for Envelope {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bool(&mut self.start);
+        s.bool(&mut self.looping);
+        s.bool(&mut self.constant);
+        s.u8_below(&mut self.volume, 16);
+        s.u8(&mut self.divider);
+        s.u8_below(&mut self.decay, 16);
+    }
+}
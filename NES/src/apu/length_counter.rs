@@ -0,0 +1,58 @@
+use crate::savestate::{Snapshot, StateStream};
+/*
+Length counter shared by the pulse, triangle and noise channels. Loaded from
+the top 5 bits of the channel's last register and silences the channel when it
+reaches zero.
+*/
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub halt: bool,
+    pub counter: u8,
+}
+impl Default for LengthCounter {
+    fn default() -> Self {
+        LengthCounter::new()
+    }
+}
+impl LengthCounter {
+    pub fn new() -> Self {
+        LengthCounter {
+            enabled: false,
+            halt: false,
+            counter: 0,
+        }
+    }
+    pub fn load(&mut self, data: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+    }
+    //$4015 writes, disabling a channel clears its counter immediately
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+    //clocked by the frame counter every half frame
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+impl Snapshot for LengthCounter {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bool(&mut self.enabled);
+        s.bool(&mut self.halt);
+        s.u8(&mut self.counter);
+    }
+}
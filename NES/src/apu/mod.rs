@@ -1 +1,231 @@
+pub mod dmc;
+pub mod envelope;
+pub mod length_counter;
+pub mod noise;
 pub mod pulse;
+pub mod triangle;
+use crate::audio::resampler::Resampler;
+use crate::savestate::{Snapshot, StateStream};
+use dmc::DMC;
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+/*
+$4015 write ---D NT21  enable dmc, noise, triangle, pulse 2, pulse 1
+$4015 read  IF-D NT21  dmc irq, frame irq, dmc active, length counters > 0
+$4017 write MI-- ----  sequencer mode (0: 4-step, 1: 5-step), frame irq inhibit
+*/
+//frame counter steps in cpu cycles since the sequence started
+const STEP_1: u32 = 7457;
+const STEP_2: u32 = 14913;
+const STEP_3: u32 = 22371;
+const STEP_4: u32 = 29829;
+const FOUR_STEP_LENGTH: u32 = 29830;
+const STEP_5: u32 = 37281;
+const FIVE_STEP_LENGTH: u32 = 37282;
+pub struct APU {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: DMC,
+    pub five_step: bool,
+    pub irq_inhibit: bool,
+    pub frame_irq: bool,
+    pub frame_cycle: u32,
+    pub cycles: u64,
+    pub resampler: Resampler,
+}
+impl Default for APU {
+    fn default() -> Self {
+        APU::new()
+    }
+}
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(1),
+            pulse2: Pulse::new(2),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: DMC::new(),
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            cycles: 0,
+            resampler: Resampler::new(44100),
+        }
+    }
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000 => self.pulse1.write_control(data),
+            0x4001 => self.pulse1.write_sweep(data),
+            0x4002 => self.pulse1.write_timer_lo(data),
+            0x4003 => self.pulse1.write_timer_hi(data),
+            0x4004 => self.pulse2.write_control(data),
+            0x4005 => self.pulse2.write_sweep(data),
+            0x4006 => self.pulse2.write_timer_lo(data),
+            0x4007 => self.pulse2.write_timer_hi(data),
+            0x4008 => self.triangle.write_linear(data),
+            0x400A => self.triangle.write_timer_lo(data),
+            0x400B => self.triangle.write_timer_hi(data),
+            0x400C => self.noise.write_control(data),
+            0x400E => self.noise.write_period(data),
+            0x400F => self.noise.write_length(data),
+            0x4010 => self.dmc.write_control(data),
+            0x4011 => self.dmc.write_output(data),
+            0x4012 => self.dmc.write_address(data),
+            0x4013 => self.dmc.write_length(data),
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0b0000_0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0000_0010 != 0);
+                self.triangle.length.set_enabled(data & 0b0000_0100 != 0);
+                self.noise.length.set_enabled(data & 0b0000_1000 != 0);
+                self.dmc.set_enabled(data & 0b0001_0000 != 0);
+            }
+            0x4017 => {
+                self.five_step = data & 0b1000_0000 != 0;
+                self.irq_inhibit = data & 0b0100_0000 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                //5-step mode clocks the units immediately on write
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => { /* unused registers $4009 and $400D */ }
+        }
+    }
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.pulse1.length.active() {
+            status = status | 0b0000_0001;
+        }
+        if self.pulse2.length.active() {
+            status = status | 0b0000_0010;
+        }
+        if self.triangle.length.active() {
+            status = status | 0b0000_0100;
+        }
+        if self.noise.length.active() {
+            status = status | 0b0000_1000;
+        }
+        if self.dmc.bytes_remaining > 0 {
+            status = status | 0b0001_0000;
+        }
+        if self.frame_irq {
+            status = status | 0b0100_0000;
+        }
+        if self.dmc.irq_flag {
+            status = status | 0b1000_0000;
+        }
+        //reading acknowledges the frame interrupt
+        self.frame_irq = false;
+        status
+    }
+    //advances the apu by one cpu cycle
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycles % 2 == 0 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.clock_frame_counter();
+    }
+    //queues this cycle's sample, with the cartridge's expansion audio on top
+    pub fn mix(&mut self, expansion: f32) {
+        let sample = self.output() + expansion;
+        self.resampler.push(sample);
+    }
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        match (self.frame_cycle, self.five_step) {
+            (STEP_1, _) | (STEP_3, _) => self.clock_quarter_frame(),
+            (STEP_2, _) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (STEP_4, false) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                if !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+            }
+            (FOUR_STEP_LENGTH, false) => self.frame_cycle = 0,
+            (STEP_5, true) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (FIVE_STEP_LENGTH, true) => self.frame_cycle = 0,
+            _ => {}
+        }
+    }
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+    fn clock_half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+    //non-linear mixer, returns a sample in the 0.0..1.0 range
+    pub fn output(&self) -> f32 {
+        pulse_mix(self.pulse1.output() + self.pulse2.output())
+            + tnd_mix(
+                self.triangle.output(),
+                self.noise.output(),
+                self.dmc.output(),
+            )
+    }
+}
+//one pulse at full volume, expansion audio levels are given relative to this
+pub const PULSE_LEVEL: f32 = 95.88 / (8128.0 / 15.0 + 100.0);
+//the two halves of the mixer, also used for expansion channels that copy the apu's
+pub fn pulse_mix(pulse: u8) -> f32 {
+    if pulse == 0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse as f32 + 100.0)
+    }
+}
+pub fn tnd_mix(triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    }
+}
+impl Snapshot for APU {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        self.pulse1.snapshot(s);
+        self.pulse2.snapshot(s);
+        self.triangle.snapshot(s);
+        self.noise.snapshot(s);
+        self.dmc.snapshot(s);
+        s.bool(&mut self.five_step);
+        s.bool(&mut self.irq_inhibit);
+        s.bool(&mut self.frame_irq);
+        let length = if self.five_step {
+            FIVE_STEP_LENGTH
+        } else {
+            FOUR_STEP_LENGTH
+        };
+        s.u32_range(&mut self.frame_cycle, 0..=length - 1);
+        s.u64(&mut self.cycles);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::savestate::{Snapshot, StateStream};
+/*
+$400C --LC VVVV  length halt/envelope loop, constant volume, volume
+$400E M--- PPPP  mode (short loop), period index
+$400F LLLL L---  length counter load
+*/
+//ntsc periods in cpu cycles
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+pub struct Noise {
+    pub shift: u16,
+    pub mode: bool,
+    pub timer: u16,
+    pub timer_period: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+impl Default for Noise {
+    fn default() -> Self {
+        Noise::new()
+    }
+}
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            shift: 1,
+            mode: false,
+            timer: 0,
+            timer_period: NOISE_PERIODS[0],
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+        }
+    }
+    pub fn write_control(&mut self, data: u8) {
+        self.length.halt = data & 0b0010_0000 != 0;
+        self.envelope.write(data);
+    }
+    pub fn write_period(&mut self, data: u8) {
+        self.mode = data & 0b1000_0000 != 0;
+        self.timer_period = NOISE_PERIODS[(data & 0b1111) as usize];
+    }
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load(data);
+        self.envelope.start = true;
+    }
+    //clocked every cpu cycle, the period table is already in cpu cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            //15 bit lfsr, mode 1 taps bit 6 instead of bit 1 for a 93 step loop
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift & 1) ^ ((self.shift >> tap) & 1);
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 == 1 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+impl Snapshot for Noise {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u16(&mut self.shift);
+        s.bool(&mut self.mode);
+        s.u16(&mut self.timer);
+        s.u16_of(&mut self.timer_period, &NOISE_PERIODS);
+        self.envelope.snapshot(s);
+        self.length.snapshot(s);
+    }
+}
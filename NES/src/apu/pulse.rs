@@ -1,16 +1,140 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::savestate::{Snapshot, StateStream};
+/*
+$4000/$4004 DDLC VVVV  duty, length halt/envelope loop, constant volume, volume
+$4001/$4005 EPPP NSSS  sweep enable, divider period, negate, shift count
+$4002/$4006 TTTT TTTT  timer low 8 bits
+$4003/$4007 LLLL LTTT  length counter load, timer high 3 bits
+*/
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
 pub struct Pulse {
-    pub bits0: u8,
-    pub bits1: u8,
-    pub bits2: u8,
-    pub bits3: u8,
+    //pulse 1 negates with ones' complement, pulse 2 with twos' complement
+    pub channel: u8,
+    pub duty: u8,
+    pub sequence: u8,
+    pub timer: u16,
+    pub timer_period: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    pub sweep_enabled: bool,
+    pub sweep_period: u8,
+    pub sweep_negate: bool,
+    pub sweep_shift: u8,
+    pub sweep_divider: u8,
+    pub sweep_reload: bool,
 }
 impl Pulse {
-    pub fn new() -> Self {
+    pub fn new(channel: u8) -> Self {
         Pulse {
-            bits0: 0b0000_0000,
-            bits1: 0b0000_0000,
-            bits2: 0b0000_0000,
-            bits3: 0b0000_0000,
+            channel: channel,
+            duty: 0,
+            sequence: 0,
+            timer: 0,
+            timer_period: 0,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
         }
     }
+    pub fn write_control(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.length.halt = data & 0b0010_0000 != 0;
+        self.envelope.write(data);
+    }
+    pub fn write_sweep(&mut self, data: u8) {
+        self.sweep_enabled = data & 0b1000_0000 != 0;
+        self.sweep_period = (data >> 4) & 0b111;
+        self.sweep_negate = data & 0b0000_1000 != 0;
+        self.sweep_shift = data & 0b111;
+        self.sweep_reload = true;
+    }
+    pub fn write_timer_lo(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0xff00) | data as u16;
+    }
+    pub fn write_timer_hi(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x00ff) | ((data as u16 & 0b111) << 8);
+        self.length.load(data);
+        self.sequence = 0;
+        self.envelope.start = true;
+    }
+    //clocked every apu cycle (every other cpu cycle)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence = (self.sequence + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            let extra = if self.channel == 1 { 1 } else { 0 };
+            self.timer_period.saturating_sub(change + extra)
+        } else {
+            self.timer_period + change
+        }
+    }
+    //the sweep unit mutes the channel even while disabled
+    fn sweep_muting(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7ff
+    }
+    //clocked by the frame counter every half frame
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0
+            && self.sweep_enabled
+            && self.sweep_shift > 0
+            && !self.sweep_muting()
+        {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+    pub fn output(&self) -> u8 {
+        if self.sweep_muting() {
+            0
+        } else {
+            self.unswept_output()
+        }
+    }
+    //output without the sweep unit's muting, MMC5's copies of the channel have no sweep
+    pub fn unswept_output(&self) -> u8 {
+        if !self.length.active() || DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+impl Snapshot for Pulse {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u8_below(&mut self.duty, 4);
+        s.u8_below(&mut self.sequence, 8);
+        s.u16(&mut self.timer);
+        s.u16_range(&mut self.timer_period, 0..=0x7ff);
+        self.envelope.snapshot(s);
+        self.length.snapshot(s);
+        s.bool(&mut self.sweep_enabled);
+        s.u8_below(&mut self.sweep_period, 8);
+        s.bool(&mut self.sweep_negate);
+        s.u8_below(&mut self.sweep_shift, 8);
+        s.u8(&mut self.sweep_divider);
+        s.bool(&mut self.sweep_reload);
+    }
 }
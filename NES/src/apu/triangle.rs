@@ -0,0 +1,92 @@
+use crate::apu::length_counter::LengthCounter;
+use crate::savestate::{Snapshot, StateStream};
+/*
+$4008 CRRR RRRR  length halt/linear control, linear counter reload value
+$400A TTTT TTTT  timer low 8 bits
+$400B LLLL LTTT  length counter load, timer high 3 bits
+*/
+#[rustfmt::skip]
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
+     0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15,
+];
+pub struct Triangle {
+    pub sequence: u8,
+    pub timer: u16,
+    pub timer_period: u16,
+    pub length: LengthCounter,
+    pub control: bool,
+    pub linear_reload_value: u8,
+    pub linear_counter: u8,
+    pub linear_reload: bool,
+}
+impl Default for Triangle {
+    fn default() -> Self {
+        Triangle::new()
+    }
+}
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            sequence: 0,
+            timer: 0,
+            timer_period: 0,
+            length: LengthCounter::new(),
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+        }
+    }
+    pub fn write_linear(&mut self, data: u8) {
+        self.control = data & 0b1000_0000 != 0;
+        self.length.halt = self.control;
+        self.linear_reload_value = data & 0b0111_1111;
+    }
+    pub fn write_timer_lo(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0xff00) | data as u16;
+    }
+    pub fn write_timer_hi(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x00ff) | ((data as u16 & 0b111) << 8);
+        self.length.load(data);
+        self.linear_reload = true;
+    }
+    //clocked every cpu cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            //ultrasonic periods are inaudible on hardware and only pop through speakers
+            if self.length.active() && self.linear_counter > 0 && self.timer_period >= 2 {
+                self.sequence = (self.sequence + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+    //clocked by the frame counter every quarter frame
+    pub fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+    pub fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence as usize]
+    }
+}
+impl Snapshot for Triangle {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u8_below(&mut self.sequence, 32);
+        s.u16(&mut self.timer);
+        s.u16(&mut self.timer_period);
+        self.length.snapshot(s);
+        s.bool(&mut self.control);
+        s.u8(&mut self.linear_reload_value);
+        s.u8(&mut self.linear_counter);
+        s.bool(&mut self.linear_reload);
+    }
+}
@@ -0,0 +1,2 @@
+pub mod resampler;
+pub mod ring_buffer;
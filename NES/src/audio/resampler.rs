@@ -0,0 +1,139 @@
+use std::f32::consts::PI;
+/*
+Turns the apu's one-sample-per-cpu-cycle stream into audio at a host sample
+rate, band-limited the way blip_buf does it. The input only matters where it
+changes, so every change is added to the output as a step, drawn with a
+windowed-sinc kernel centred on the exact (sub-sample) time it happened, and
+the output is the running sum of those steps. The kernel cuts off a little
+below the output Nyquist frequency, so nothing above it folds back as aliasing.
+The result goes through the filter chain of the console's analog output: a
+14kHz low-pass and 90Hz/440Hz high-passes.
+ */
+pub const CPU_CLOCK: f64 = 1_789_773.0;
+//kernel taps on each side of a step and sub-sample positions it is tabulated for
+const HALF_WIDTH: usize = 8;
+const KERNEL_WIDTH: usize = HALF_WIDTH * 2;
+const PHASES: usize = 64;
+//kernel cutoff as a fraction of the output rate, just under Nyquist
+const CUTOFF: f32 = 0.45;
+//kernel[phase][tap], how a step landing phase/PHASES into an output sample is spread
+//over the following ones: a blackman windowed sinc, scaled so every phase sums to one
+fn step_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    (0..PHASES)
+        .map(|phase| {
+            let mut taps = [0.0; KERNEL_WIDTH];
+            for (tap, value) in taps.iter_mut().enumerate() {
+                let x = tap as f32 - (HALF_WIDTH - 1) as f32 - phase as f32 / PHASES as f32;
+                let sinc = if x == 0.0 {
+                    2.0 * CUTOFF
+                } else {
+                    (2.0 * PI * CUTOFF * x).sin() / (PI * x)
+                };
+                let w = 2.0 * PI * (x / KERNEL_WIDTH as f32 + 0.5);
+                *value = sinc * (0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos());
+            }
+            let sum: f32 = taps.iter().sum();
+            taps.iter_mut().for_each(|value| *value /= sum);
+            taps
+        })
+        .collect()
+}
+struct LowPass {
+    alpha: f32,
+    prev_out: f32,
+}
+impl LowPass {
+    fn new(cutoff: f32, rate: f32) -> Self {
+        LowPass {
+            alpha: 1.0 - (-2.0 * PI * cutoff / rate).exp(),
+            prev_out: 0.0,
+        }
+    }
+    fn process(&mut self, sample: f32) -> f32 {
+        self.prev_out += self.alpha * (sample - self.prev_out);
+        self.prev_out
+    }
+}
+struct HighPass {
+    alpha: f32,
+    prev_in: f32,
+    prev_out: f32,
+}
+impl HighPass {
+    fn new(cutoff: f32, rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        HighPass {
+            alpha: rc / (rc + 1.0 / rate),
+            prev_in: 0.0,
+            prev_out: 0.0,
+        }
+    }
+    fn process(&mut self, sample: f32) -> f32 {
+        self.prev_out = self.alpha * (self.prev_out + sample - self.prev_in);
+        self.prev_in = sample;
+        self.prev_out
+    }
+}
+pub struct Resampler {
+    pub output_rate: u32,
+    //output samples per cpu cycle
+    step: f64,
+    //position inside the output sample being built, 0.0..1.0
+    phase: f64,
+    last_input: f32,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    //steps spread over the next KERNEL_WIDTH output samples, not yet summed
+    pending: [f32; KERNEL_WIDTH],
+    level: f32,
+    lowpass: LowPass,
+    highpass_90: HighPass,
+    highpass_440: HighPass,
+    pub samples: Vec<f32>,
+}
+impl Resampler {
+    pub fn new(output_rate: u32) -> Self {
+        Resampler {
+            output_rate: output_rate,
+            step: output_rate as f64 / CPU_CLOCK,
+            phase: 0.0,
+            last_input: 0.0,
+            kernel: step_kernel(),
+            pending: [0.0; KERNEL_WIDTH],
+            level: 0.0,
+            lowpass: LowPass::new(14000.0, output_rate as f32),
+            highpass_90: HighPass::new(90.0, output_rate as f32),
+            highpass_440: HighPass::new(440.0, output_rate as f32),
+            samples: Vec::new(),
+        }
+    }
+    pub fn push(&mut self, sample: f32) {
+        let delta = sample - self.last_input;
+        if delta != 0.0 {
+            self.last_input = sample;
+            let phase = (self.phase * PHASES as f64) as usize;
+            let kernel = &self.kernel[phase.min(PHASES - 1)];
+            for (pending, tap) in self.pending.iter_mut().zip(kernel.iter()) {
+                *pending += delta * tap;
+            }
+        }
+        self.phase += self.step;
+        if self.phase < 1.0 {
+            return;
+        }
+        self.phase -= 1.0;
+        self.level += self.pending[0];
+        self.pending.copy_within(1.., 0);
+        self.pending[KERNEL_WIDTH - 1] = 0.0;
+        let filtered = self.lowpass.process(self.level);
+        let out = self
+            .highpass_440
+            .process(self.highpass_90.process(filtered));
+        //hold at most a second of audio when nobody is draining it (headless runs)
+        if self.samples.len() < self.output_rate as usize {
+            self.samples.push(out);
+        }
+    }
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+/*
+Single producer, single consumer ring buffer of f32 samples. The emulation
+thread pushes and the audio callback pops; neither side ever takes a lock.
+Samples are stored as their bit patterns in atomics so the slots can be shared.
+ */
+struct Shared {
+    slots: Box<[AtomicU32]>,
+    head: AtomicUsize, //next slot to read, only written by the consumer
+    tail: AtomicUsize, //next slot to write, only written by the producer
+}
+impl Shared {
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + self.slots.len() - head) % self.slots.len()
+    }
+}
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+//one slot stays empty to tell a full buffer from an empty one
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    let slots = (0..capacity + 1).map(|_| AtomicU32::new(0)).collect();
+    let shared = Arc::new(Shared {
+        slots: slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared: shared },
+    )
+}
+impl Producer {
+    //returns false and drops the sample when the buffer is full
+    pub fn push(&mut self, sample: f32) -> bool {
+        let shared = &self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % shared.slots.len();
+        if next == shared.head.load(Ordering::Acquire) {
+            return false;
+        }
+        shared.slots[tail].store(sample.to_bits(), Ordering::Relaxed);
+        shared.tail.store(next, Ordering::Release);
+        true
+    }
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+}
+impl Consumer {
+    pub fn pop(&mut self) -> Option<f32> {
+        let shared = &self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        if head == shared.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let sample = f32::from_bits(shared.slots[head].load(Ordering::Relaxed));
+        shared
+            .head
+            .store((head + 1) % shared.slots.len(), Ordering::Release);
+        Some(sample)
+    }
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+}
@@ -0,0 +1,48 @@
+use crate::Console;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+/*
+Battery backed prg-ram kept in a .sav file next to the rom. The file is a raw copy
+of the cartridge's prg-ram, the format other emulators use. Frontends call flush
+every so often and on exit, it only touches the disk when the ram has changed.
+ */
+pub struct SaveFile {
+    pub path: PathBuf,
+    pub last_written: Vec<u8>,
+}
+impl SaveFile {
+    pub fn new(rom_path: &Path) -> Self {
+        SaveFile {
+            path: rom_path.with_extension("sav"),
+            last_written: Vec::new(),
+        }
+    }
+    //fills the console's battery ram from the file, a missing file is a fresh cartridge
+    pub fn load(&mut self, console: &mut Console) -> io::Result<()> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                console.load_battery_ram(&data);
+                self.last_written = console.battery_ram().unwrap_or_default();
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+    pub fn flush(&mut self, console: &Console) -> io::Result<()> {
+        let ram = match console.battery_ram() {
+            Some(ram) => ram,
+            None => return Ok(()),
+        };
+        if ram == self.last_written {
+            return Ok(());
+        }
+        //write beside the old save and rename over it, so a crash mid write keeps the old one
+        let temp = self.path.with_extension("sav.tmp");
+        fs::write(&temp, &ram)?;
+        fs::rename(&temp, &self.path)?;
+        self.last_written = ram;
+        Ok(())
+    }
+}
@@ -1,13 +1,6 @@
-/*pub mod bus;
-pub mod cpu;
-pub mod opcodes;
-pub mod ppu;
-pub mod ppu_utils;
-pub mod render;
-pub mod rom;*/
-use crate::render::frame::Frame;
-use crate::render::palette;
-use crate::rom::Rom;
+use nes::render::frame::Frame;
+use nes::render::palette;
+use nes::rom::Rom;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
@@ -0,0 +1,167 @@
+use nes::movie::Movie;
+use nes::rom::Rom;
+use nes::savestate::fnv1a;
+use nes::Console;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+/*
+Headless regression runner. Each rom in the manifest is run for a number of frames,
+optionally driven by an input movie, and the final frame buffer is hashed and
+compared against the golden value.
+
+    regress <manifest> [--bless]
+
+Manifest lines, paths relative to the manifest, # starts a comment:
+    <rom> <frames> <fnv-1a hash of Frame::data, or - when unknown> [movie]
+Movies are our own format or FCEUX .fm2, frames after the movie ends have no input.
+--bless writes the hashes the current build produces back into the manifest.
+ */
+struct Case {
+    rom: String,
+    frames: usize,
+    golden: Option<u64>,
+    movie: Option<String>,
+}
+fn parse_line(line: &str) -> Result<Option<Case>, String> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 3 || fields.len() > 4 {
+        return Err(String::from("expected <rom> <frames> <hash> [movie]"));
+    }
+    let frames = fields[1]
+        .parse()
+        .map_err(|_| format!("bad frame count {}", fields[1]))?;
+    let golden = match fields[2] {
+        "-" => None,
+        hash => Some(u64::from_str_radix(hash, 16).map_err(|_| format!("bad hash {}", hash))?),
+    };
+    Ok(Some(Case {
+        rom: fields[0].to_string(),
+        frames: frames,
+        golden: golden,
+        movie: fields.get(3).map(|movie| movie.to_string()),
+    }))
+}
+fn run(case: &Case, dir: &Path) -> Result<u64, String> {
+    let raw = fs::read(dir.join(&case.rom)).map_err(|e| format!("{}: {}", case.rom, e))?;
+    let rom = Rom::new(&raw).map_err(|e| format!("{}: {}", case.rom, e))?;
+    let mut console = Console::new(rom).map_err(|e| format!("{}: {}", case.rom, e))?;
+    let movie = match &case.movie {
+        Some(file) => {
+            let data = fs::read(dir.join(file)).map_err(|e| format!("{}: {}", file, e))?;
+            let movie = if file.ends_with(".fm2") {
+                Movie::from_fm2(&String::from_utf8_lossy(&data))
+            } else {
+                Movie::from_bytes(&data)
+            };
+            let movie = movie.map_err(|e| format!("{}: {}", file, e))?;
+            movie
+                .start(&mut console)
+                .map_err(|e| format!("{}: {}", file, e))?;
+            Some(movie)
+        }
+        None => None,
+    };
+    for frame in 0..case.frames {
+        let playing = match &movie {
+            Some(movie) => movie.play_frame(&mut console, frame),
+            None => false,
+        };
+        if !playing {
+            console.set_buttons(0);
+        }
+        console.step_frame();
+    }
+    Ok(fnv1a(&console.framebuffer().data))
+}
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let manifest_path = match args.get(1) {
+        Some(path) => path,
+        None => {
+            println!("usage: regress <manifest> [--bless]");
+            process::exit(2);
+        }
+    };
+    let bless = args.iter().any(|arg| arg == "--bless");
+    let manifest = fs::read_to_string(manifest_path).unwrap_or_else(|e| {
+        println!("{}: {}", manifest_path, e);
+        process::exit(2);
+    });
+    let dir = Path::new(manifest_path).parent().unwrap_or(Path::new("."));
+    let mut blessed = String::new();
+    let (mut passed, mut failed) = (0, 0);
+    for (index, line) in manifest.lines().enumerate() {
+        let case = match parse_line(line) {
+            Ok(Some(case)) => case,
+            Ok(None) => {
+                blessed.push_str(line);
+                blessed.push('\n');
+                continue;
+            }
+            Err(e) => {
+                println!("ERROR {}:{}: {}", manifest_path, index + 1, e);
+                blessed.push_str(line);
+                blessed.push('\n');
+                failed += 1;
+                continue;
+            }
+        };
+        let hash = match run(&case, dir) {
+            Ok(hash) => hash,
+            Err(e) => {
+                println!("ERROR {}", e);
+                blessed.push_str(line);
+                blessed.push('\n');
+                failed += 1;
+                continue;
+            }
+        };
+        match case.golden {
+            Some(golden) if golden == hash => {
+                println!("ok    {} ({} frames)", case.rom, case.frames);
+                passed += 1;
+            }
+            Some(golden) => {
+                println!(
+                    "FAIL  {} ({} frames): expected {:016x}, got {:016x}",
+                    case.rom, case.frames, golden, hash
+                );
+                failed += 1;
+            }
+            None => {
+                println!("new   {} ({} frames): {:016x}", case.rom, case.frames, hash);
+                failed += 1;
+            }
+        }
+        let mut fields = vec![
+            case.rom.clone(),
+            case.frames.to_string(),
+            format!("{:016x}", hash),
+        ];
+        fields.extend(case.movie.clone());
+        blessed.push_str(&fields.join(" "));
+        if let Some(comment) = line.find('#') {
+            blessed.push_str(" ");
+            blessed.push_str(&line[comment..]);
+        }
+        blessed.push('\n');
+    }
+    if bless {
+        if let Err(e) = fs::write(manifest_path, blessed) {
+            println!("{}: {}", manifest_path, e);
+            process::exit(2);
+        }
+        println!("{} updated", manifest_path);
+        return;
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
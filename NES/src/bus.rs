@@ -1,13 +1,21 @@
+use crate::apu::APU;
 use crate::joypads::Joypad;
+use crate::mapper;
+use crate::mapper::SharedMapper;
 use crate::ppu::PPU;
-use crate::rom::Rom;
+use crate::rom::{Rom, RomError};
+use crate::savestate::{Snapshot, StateStream};
 pub struct Bus<'call> {
     cpu_vram: [u8; 2048],
-    prg_rom: Vec<u8>,
+    pub mapper: SharedMapper,
+    //bytes at the end of the cartridge's prg-ram kept alive by a battery, 0 without one
+    pub battery_ram_size: usize,
     pub ppu: PPU,
+    pub apu: APU,
     pub cycles: usize,
     game_callback: Box<dyn FnMut(&PPU, &mut Joypad) + 'call>,
-    joypad1: Joypad,
+    pub joypad1: Joypad,
+    frame_ready: bool,
 }
 pub trait Memory {
     fn mem_read(&mut self, addr: u16) -> u8;
@@ -32,41 +40,72 @@ const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRROR: u16 = 0x3FFF;
 
 impl<'a> Bus<'a> {
-    pub fn new<'call, F>(rom: Rom, game_callback: F) -> Bus<'call>
+    pub fn new<'call, F>(rom: Rom, game_callback: F) -> Result<Bus<'call>, RomError>
     where
         F: FnMut(&PPU, &mut Joypad) + 'call,
     {
-        let ppu = PPU::new(rom.chr_rom, rom.screen_mirroring);
-        Bus {
+        let battery_ram_size = if rom.header.battery {
+            rom.header.prg_nvram_size
+        } else {
+            0
+        };
+        let mapper = mapper::new(rom)?;
+        let ppu = PPU::new(mapper.clone());
+        Ok(Bus {
             cpu_vram: [0; 2048],
-            prg_rom: rom.prg_rom,
+            mapper: mapper,
+            battery_ram_size: battery_ram_size,
             ppu: ppu,
+            apu: APU::new(),
             cycles: 0,
             game_callback: Box::from(game_callback),
             joypad1: Joypad::new(),
-        }
-    }
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            //mirroring
-            addr = addr % 0x4000;
-        }
-        self.prg_rom[addr as usize]
+            frame_ready: false,
+        })
     }
     pub fn tick(&mut self, cycles: u8) {
         //println!("bus cycles: {}", self.cycles);
-        self.cycles += cycles as usize;
+        for _ in 0..cycles {
+            self.tick_cycle();
+        }
+    }
+    fn tick_cycle(&mut self) {
+        self.cycles += 1;
         let before_nmi = self.ppu.nmi_interrupt.is_some();
-        self.ppu.tick(cycles * 3);
+        if self.ppu.tick(3) {
+            self.frame_ready = true;
+        }
         let after_nmi = self.ppu.nmi_interrupt.is_some();
         if !before_nmi && after_nmi {
             (self.game_callback)(&self.ppu, &mut self.joypad1);
         }
+        self.apu.tick();
+        let expansion = {
+            let mut mapper = self.mapper.borrow_mut();
+            mapper.clock();
+            mapper.audio_output()
+        };
+        self.apu.mix(expansion);
+        if let Some(addr) = self.apu.dmc.dma_request() {
+            let data = self.mapper.borrow_mut().read_prg(addr);
+            self.apu.dmc.dma_fill(data);
+            //the cpu is stalled while the dmc steals the bus
+            for _ in 0..4 {
+                self.tick_cycle();
+            }
+        }
     }
     pub fn poll_nmi(&mut self) -> Option<u8> {
         self.ppu.poll_nmi()
     }
+    //level triggered irq line shared by the apu frame counter, dmc and cartridge
+    pub fn poll_irq(&self) -> bool {
+        self.apu.frame_irq || self.apu.dmc.irq_flag || self.mapper.borrow().irq_pending()
+    }
+    //true once per frame, after the ppu wraps back around to the pre-render scanline
+    pub fn poll_frame(&mut self) -> bool {
+        std::mem::replace(&mut self.frame_ready, false)
+    }
 }
 impl Memory for Bus<'_> {
     fn mem_read(&mut self, addr: u16) -> u8 {
@@ -86,8 +125,9 @@ impl Memory for Bus<'_> {
                 let ppu_mirror_addr = addr & 0b00100000_00000111;
                 self.mem_read(ppu_mirror_addr)
             }
-            0x4000..=0x4015 => {
-                //apu not yet implemented
+            0x4015 => self.apu.read_status(),
+            0x4000..=0x4013 => {
+                //apu channel registers are write-only
                 0
             }
             0x4016 => {
@@ -98,7 +138,7 @@ impl Memory for Bus<'_> {
                 //joypad 2
                 0
             }
-            0x8000..=0xFFFF => self.read_prg_rom(addr),
+            0x4020..=0xFFFF => self.mapper.borrow_mut().read_prg(addr),
             _ => {
                 println!("Cannot read memory at {:x}!", addr);
                 0
@@ -106,6 +146,9 @@ impl Memory for Bus<'_> {
         }
     }
     fn mem_write(&mut self, addr: u16, data: u8) {
+        if let 0x2000..=0x2007 = addr {
+            self.mapper.borrow_mut().snoop_ppu_write(addr, data);
+        }
         match addr {
             RAM..=RAM_MIRROR => {
                 let mirror_addr = addr & 0b00000111_11111111;
@@ -140,14 +183,15 @@ impl Memory for Bus<'_> {
                 self.mem_write(ppu_mirror_addr, data);
             }
             0x4000..=0x4013 | 0x4015 => {
-                //apu not yet implemented
+                self.apu.write_register(addr, data);
             }
             0x4016 => {
                 //joypad 1
                 self.joypad1.write(data);
             }
             0x4017 => {
-                //joypad 2
+                //apu frame counter, joypad 2 is only on the read side
+                self.apu.write_register(addr, data);
             }
             0x4014 => {
                 //oam dma
@@ -159,8 +203,8 @@ impl Memory for Bus<'_> {
                 self.ppu.write_oam_dma(&buffer);
                 //must implement correct cycle logic for oam dma write.
             }
-            0x8000..=0xFFFF => {
-                panic!("Attempt to write to cartridge ROM space!")
+            0x4020..=0xFFFF => {
+                self.mapper.borrow_mut().write_prg(addr, data);
             }
             _ => {
                 println!("Cannot write {} to {:x}!", data, addr);
@@ -168,3 +212,14 @@ impl Memory for Bus<'_> {
         }
     }
 }
+impl Snapshot for Bus<'_> {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.cpu_vram);
+        s.usize(&mut self.cycles);
+        s.bool(&mut self.frame_ready);
+        self.joypad1.snapshot(s);
+        self.ppu.snapshot(s);
+        self.apu.snapshot(s);
+        self.mapper.borrow_mut().snapshot(s);
+    }
+}
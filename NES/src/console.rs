@@ -0,0 +1,144 @@
+use crate::audio::resampler::Resampler;
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use crate::joypads::Button;
+use crate::render::frame::Frame;
+use crate::rom::{Rom, RomError};
+use crate::savestate::{fnv1a, Snapshot, StateError, StateStream};
+use crate::savestate::{STATE_HEADER_SIZE, STATE_MAGIC, STATE_VERSION};
+/*
+Headless front door to the emulator. Owns the cpu (which owns the bus, ppu and
+cartridge) so frontends never have to touch the nmi callback plumbing in Bus.
+ */
+pub struct Console {
+    pub cpu: CPU<'static>,
+    //identifies the cartridge in save states
+    pub rom_hash: u64,
+}
+impl Console {
+    //fails when there is no mapper for the cartridge
+    pub fn new(rom: Rom) -> Result<Self, RomError> {
+        Ok(Console {
+            rom_hash: Console::hash_rom(&rom),
+            cpu: Console::power_on(rom)?,
+        })
+    }
+    fn hash_rom(rom: &Rom) -> u64 {
+        let mut data = rom.prg_rom.clone();
+        data.extend_from_slice(&rom.chr_rom);
+        fnv1a(&data)
+    }
+    fn power_on(rom: Rom) -> Result<CPU<'static>, RomError> {
+        let bus = Bus::new(rom, |_, _| {})?;
+        let mut cpu = CPU::new(bus);
+        cpu.reset();
+        cpu.bus.tick(7); //reset sequence takes 7 cycles
+        Ok(cpu)
+    }
+    //swaps in a new cartridge and powers the machine back on
+    pub fn load_rom(&mut self, raw: &[u8]) -> Result<(), RomError> {
+        let rom = Rom::new(raw)?;
+        let rom_hash = Console::hash_rom(&rom);
+        self.cpu = Console::power_on(rom)?;
+        self.rom_hash = rom_hash;
+        Ok(())
+    }
+    //runs one cpu instruction
+    pub fn step_instruction(&mut self) {
+        self.cpu.step();
+    }
+    //runs until the ppu finishes the current frame
+    pub fn step_frame(&mut self) {
+        loop {
+            self.cpu.step();
+            if self.cpu.bus.poll_frame() {
+                return;
+            }
+        }
+    }
+    pub fn framebuffer(&self) -> &Frame {
+        &self.cpu.bus.ppu.frame
+    }
+    //bit layout matches Joypad::get_button, A is bit 0 and Right is bit 7
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.cpu.bus.joypad1.button_status = buttons;
+    }
+    pub fn set_button(&mut self, button: &Button, pressed: bool) {
+        self.cpu.bus.joypad1.set_button(button, pressed);
+    }
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.cpu.bus.apu.resampler = Resampler::new(rate);
+    }
+    //contents of battery backed prg-ram, None when the cartridge has no battery
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        let mut mapper = self.cpu.bus.mapper.borrow_mut();
+        battery_part(mapper.prg_ram()?, self.cpu.bus.battery_ram_size).map(|ram| ram.to_vec())
+    }
+    //restores battery backed prg-ram, a short file fills only the start of it
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        let size = self.cpu.bus.battery_ram_size;
+        let mut mapper = self.cpu.bus.mapper.borrow_mut();
+        if let Some(ram) = mapper.prg_ram().and_then(|ram| battery_part(ram, size)) {
+            let len = ram.len().min(data.len());
+            ram[..len].copy_from_slice(&data[..len]);
+        }
+    }
+    //audio produced since the last call, at the rate given to set_sample_rate (44.1kHz default)
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.bus.apu.resampler.take_samples()
+    }
+    //serializes the whole machine, see savestate.rs for the layout
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut s = StateStream::saver();
+        s.data.extend_from_slice(&STATE_MAGIC);
+        s.data.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        s.data.extend_from_slice(&self.rom_hash.to_le_bytes());
+        self.cpu.snapshot(&mut s);
+        s.data
+    }
+    //a state that fails to load leaves the machine as it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        if data.len() < STATE_HEADER_SIZE || data[0..4] != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut hash = [0; 8];
+        hash.copy_from_slice(&data[6..14]);
+        let found = u64::from_le_bytes(hash);
+        if found != self.rom_hash {
+            return Err(StateError::WrongRom {
+                expected: self.rom_hash,
+                found: found,
+            });
+        }
+        let backup = self.save_state();
+        let mut s = StateStream::loader(&data[STATE_HEADER_SIZE..]);
+        self.cpu.snapshot(&mut s);
+        let error = if s.overrun || s.position != s.data.len() {
+            StateError::BadLength {
+                expected: backup.len(),
+                found: data.len(),
+            }
+        } else if let Some(position) = s.bad_value {
+            StateError::BadValue {
+                offset: STATE_HEADER_SIZE + position,
+            }
+        } else {
+            return Ok(());
+        };
+        let mut restore = StateStream::loader(&backup[STATE_HEADER_SIZE..]);
+        self.cpu.snapshot(&mut restore);
+        Err(error)
+    }
+}
+//battery backed ram comes after any plain prg-ram, as on SOROM
+fn battery_part(ram: &mut [u8], size: usize) -> Option<&mut [u8]> {
+    if size == 0 {
+        return None;
+    }
+    let start = ram.len().saturating_sub(size);
+    Some(&mut ram[start..])
+}
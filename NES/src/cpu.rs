@@ -1,6 +1,7 @@
 use crate::bus::Bus;
 use crate::bus::Memory;
 use crate::opcodes;
+use crate::savestate::{Snapshot, StateStream};
 use std::collections::HashMap;
 #[allow(non_camel_case_types)]
 #[derive(Debug)]
@@ -34,6 +35,8 @@ pub struct CPU<'a> {
     pub stack_ptr: u8,
     pub program_counter: u16,
     pub bus: Bus<'a>,
+    //i flag as seen by the irq poll, lags one instruction behind CLI/SEI/PLP
+    pub irq_inhibit: bool,
 }
 const STACK_OFFSET: u16 = 0x100;
 const STACK_RESET: u8 = 0xfd;
@@ -73,6 +76,7 @@ impl<'a> CPU<'a> {
             stack_ptr: STACK_RESET, //starts at 1fd per hardware specification
             program_counter: 0,
             bus: bus,
+            irq_inhibit: true,
         }
     }
     fn stack_pop_u16(&mut self) -> u16 {
@@ -100,6 +104,7 @@ impl<'a> CPU<'a> {
         self.register_y = 0;
         self.status = 0b0010_0100;
         self.stack_ptr = STACK_RESET;
+        self.irq_inhibit = true;
         self.program_counter = self.mem_read_u16(0xFFFC);
     }
     pub fn load(&mut self, program: Vec<u8>) {
@@ -469,6 +474,13 @@ impl<'a> CPU<'a> {
         self.set_zn_flags_v1(self.register_y);
     }
     fn interrupt_nmi(&mut self) {
+        self.interrupt(0xfffa);
+    }
+    fn interrupt_irq(&mut self) {
+        self.interrupt(0xfffe);
+    }
+    //hardware interrupt sequence, pushes pc and status with the break flag clear
+    fn interrupt(&mut self, vector: u16) {
         self.stack_push_u16(self.program_counter);
         let flagcopy = self.status.clone();
         self.disable_flag(&Flag::Break);
@@ -476,8 +488,9 @@ impl<'a> CPU<'a> {
         self.stack_push(self.status);
         self.status = flagcopy;
         self.enable_flag(&Flag::IRQ);
-        self.bus.tick(2);
-        self.program_counter = self.mem_read_u16(0xfffa);
+        self.irq_inhibit = true;
+        self.bus.tick(7);
+        self.program_counter = self.mem_read_u16(vector);
     }
     fn lda(&mut self, mode: &AddressingMode) {
         let (addr, page_cross) = self.get_operand_addressing_mode(mode);
@@ -641,900 +654,972 @@ impl<'a> CPU<'a> {
         }
     }
     pub fn run(&mut self) {
-        self.run_with_callback(|_| {});
+        self.run_with_callback(|_| true);
     }
+    //the callback sees the cpu before every instruction, returning false stops the run
     pub fn run_with_callback<F>(&mut self, mut callback: F)
     where
-        F: FnMut(&mut CPU),
+        F: FnMut(&mut CPU) -> bool,
     {
-        let ref opcodes: HashMap<u8, &'static opcodes::Opcode> = *opcodes::OPCODES_MAP;
         loop {
-            if let Some(_nmi) = self.bus.poll_nmi() {
-                self.interrupt_nmi();
-            }
-            callback(self);
-            let opscode = self.mem_read(self.program_counter);
-            let opscode_data = opcodes.get(&opscode).unwrap();
-            self.program_counter += 1;
-            let pccopy = self.program_counter;
-            //println!("{:#04x}", opscode);
-            match opscode {
-                /*
-                 * * * * * * * * * * ADC OPCODES * * * * * * * * * *
-                 */
-                0x69 => {
-                    //ADC-I
-                    self.adc(&AddressingMode::Immediate);
-                }
-                0x65 => {
-                    //ADC-ZP
-                    self.adc(&AddressingMode::ZeroPage);
-                }
-                0x75 => {
-                    //ADC-ZPX
-                    self.adc(&AddressingMode::ZeroPage_X);
-                }
-                0x6D => {
-                    //ADC-ABS
-                    self.adc(&AddressingMode::Absolute);
-                }
-                0x7D => {
-                    //ADC-ABSX
-                    self.adc(&AddressingMode::Absolute_X);
-                }
-                0x79 => {
-                    //ADC-ABSY
-                    self.adc(&AddressingMode::Absolute_Y);
-                }
-                0x61 => {
-                    //ADC-INDX
-                    self.adc(&AddressingMode::Indirect_X);
-                }
-                0x71 => {
-                    //ADC-INDY
-                    self.adc(&AddressingMode::Indirect_Y);
-                }
-                /*
-                 * * * * * * * * * * AND OPCODES * * * * * * * * * *
-                 */
-                0x29 => {
-                    //AND-I
-                    self.and(&AddressingMode::Immediate);
-                }
-                0x25 => {
-                    //AND-ZP
-                    self.and(&AddressingMode::ZeroPage);
-                }
-                0x35 => {
-                    //AND-ZPX
-                    self.and(&AddressingMode::ZeroPage_X);
-                }
-                0x2D => {
-                    //AND-ABS
-                    self.and(&AddressingMode::Absolute);
-                }
-                0x3D => {
-                    //AND-ABSX
-                    self.and(&AddressingMode::Absolute_X);
-                }
-                0x39 => {
-                    //AND-ABSY
-                    self.and(&AddressingMode::Absolute_Y);
-                }
-                0x21 => {
-                    //AND-INDX
-                    self.and(&AddressingMode::Indirect_X);
-                }
-                0x31 => {
-                    //AND-INDY
-                    self.and(&AddressingMode::Indirect_Y);
-                }
-                /*
-                 * * * * * * * * * * ASL OPCODES * * * * * * * * * *
-                 */
-                0x0A => {
-                    //ASL-ACC
-                    self.asl_accumulator();
-                }
-                0x06 => {
-                    //ASL-ZP
-                    self.asl(&AddressingMode::ZeroPage);
-                }
-                0x16 => {
-                    //ASL-ZPX
-                    self.asl(&AddressingMode::ZeroPage_X);
-                }
-                0x0E => {
-                    //ASL-ABS
-                    self.asl(&AddressingMode::Absolute);
-                }
-                0x1E => {
-                    //ASL-ABSX
-                    self.asl(&AddressingMode::Absolute_X);
-                }
-                /*
-                 * * * * * * * * * * Bit Test OPCODES * * * * * * * * * *
-                 */
-                0x24 => {
-                    //BIT-ZP
-                    self.bit(&AddressingMode::ZeroPage);
-                }
-                0x2C => {
-                    //BIT-ABS
-                    self.bit(&AddressingMode::Absolute);
-                }
-                /*
-                 * * * * * * * * * * Branch OPCODES * * * * * * * * * *
-                 */
-                0x90 => {
-                    //BCC-Clear
-                    self.branch_clear(&Flag::Carry);
-                }
-                0xB0 => {
-                    //BCS-Set
-                    self.branch_set(&Flag::Carry);
-                }
-                0xF0 => {
-                    //BEQ-Set
-                    self.branch_set(&Flag::Zero);
-                }
-                0xD0 => {
-                    //BNE-Clear
-                    self.branch_clear(&Flag::Zero);
-                }
-                0x30 => {
-                    //BMI-Set
-                    self.branch_set(&Flag::Negative);
-                }
-                0x10 => {
-                    //BPL-Clear
-                    self.branch_clear(&Flag::Negative);
-                }
-                0x50 => {
-                    //BVC-Clear
-                    self.branch_clear(&Flag::Overflow);
-                }
-                0x70 => {
-                    //BVS-Set
-                    self.branch_set(&Flag::Overflow);
-                }
-                /*
-                 * * * * * * * * * * Clear OPCODES * * * * * * * * * *
-                 */
-                0x18 => {
-                    //CLC
+            self.poll_interrupts();
+            if !callback(self) {
+                return;
+            }
+            self.execute_instruction();
+        }
+    }
+    //runs a single instruction, servicing any pending interrupt first
+    pub fn step(&mut self) {
+        self.poll_interrupts();
+        self.execute_instruction();
+    }
+    fn poll_interrupts(&mut self) {
+        if let Some(_nmi) = self.bus.poll_nmi() {
+            self.interrupt_nmi();
+        } else if self.bus.poll_irq() && !self.irq_inhibit {
+            self.interrupt_irq();
+        }
+    }
+    fn execute_instruction(&mut self) {
+        let ref opcodes: HashMap<u8, &'static opcodes::Opcode> = *opcodes::OPCODES_MAP;
+        let opscode = self.mem_read(self.program_counter);
+        let opscode_data = opcodes.get(&opscode).unwrap();
+        let irq_flag_before = self.get_flag_status(&Flag::IRQ);
+        self.program_counter += 1;
+        let pccopy = self.program_counter;
+        //println!("{:#04x}", opscode);
+        match opscode {
+            /*
+             * * * * * * * * * * ADC OPCODES * * * * * * * * * *
+             */
+            0x69 => {
+                //ADC-I
+                self.adc(&AddressingMode::Immediate);
+            }
+            0x65 => {
+                //ADC-ZP
+                self.adc(&AddressingMode::ZeroPage);
+            }
+            0x75 => {
+                //ADC-ZPX
+                self.adc(&AddressingMode::ZeroPage_X);
+            }
+            0x6D => {
+                //ADC-ABS
+                self.adc(&AddressingMode::Absolute);
+            }
+            0x7D => {
+                //ADC-ABSX
+                self.adc(&AddressingMode::Absolute_X);
+            }
+            0x79 => {
+                //ADC-ABSY
+                self.adc(&AddressingMode::Absolute_Y);
+            }
+            0x61 => {
+                //ADC-INDX
+                self.adc(&AddressingMode::Indirect_X);
+            }
+            0x71 => {
+                //ADC-INDY
+                self.adc(&AddressingMode::Indirect_Y);
+            }
+            /*
+             * * * * * * * * * * AND OPCODES * * * * * * * * * *
+             */
+            0x29 => {
+                //AND-I
+                self.and(&AddressingMode::Immediate);
+            }
+            0x25 => {
+                //AND-ZP
+                self.and(&AddressingMode::ZeroPage);
+            }
+            0x35 => {
+                //AND-ZPX
+                self.and(&AddressingMode::ZeroPage_X);
+            }
+            0x2D => {
+                //AND-ABS
+                self.and(&AddressingMode::Absolute);
+            }
+            0x3D => {
+                //AND-ABSX
+                self.and(&AddressingMode::Absolute_X);
+            }
+            0x39 => {
+                //AND-ABSY
+                self.and(&AddressingMode::Absolute_Y);
+            }
+            0x21 => {
+                //AND-INDX
+                self.and(&AddressingMode::Indirect_X);
+            }
+            0x31 => {
+                //AND-INDY
+                self.and(&AddressingMode::Indirect_Y);
+            }
+            /*
+             * * * * * * * * * * ASL OPCODES * * * * * * * * * *
+             */
+            0x0A => {
+                //ASL-ACC
+                self.asl_accumulator();
+            }
+            0x06 => {
+                //ASL-ZP
+                self.asl(&AddressingMode::ZeroPage);
+            }
+            0x16 => {
+                //ASL-ZPX
+                self.asl(&AddressingMode::ZeroPage_X);
+            }
+            0x0E => {
+                //ASL-ABS
+                self.asl(&AddressingMode::Absolute);
+            }
+            0x1E => {
+                //ASL-ABSX
+                self.asl(&AddressingMode::Absolute_X);
+            }
+            /*
+             * * * * * * * * * * Bit Test OPCODES * * * * * * * * * *
+             */
+            0x24 => {
+                //BIT-ZP
+                self.bit(&AddressingMode::ZeroPage);
+            }
+            0x2C => {
+                //BIT-ABS
+                self.bit(&AddressingMode::Absolute);
+            }
+            /*
+             * * * * * * * * * * Branch OPCODES * * * * * * * * * *
+             */
+            0x90 => {
+                //BCC-Clear
+                self.branch_clear(&Flag::Carry);
+            }
+            0xB0 => {
+                //BCS-Set
+                self.branch_set(&Flag::Carry);
+            }
+            0xF0 => {
+                //BEQ-Set
+                self.branch_set(&Flag::Zero);
+            }
+            0xD0 => {
+                //BNE-Clear
+                self.branch_clear(&Flag::Zero);
+            }
+            0x30 => {
+                //BMI-Set
+                self.branch_set(&Flag::Negative);
+            }
+            0x10 => {
+                //BPL-Clear
+                self.branch_clear(&Flag::Negative);
+            }
+            0x50 => {
+                //BVC-Clear
+                self.branch_clear(&Flag::Overflow);
+            }
+            0x70 => {
+                //BVS-Set
+                self.branch_set(&Flag::Overflow);
+            }
+            /*
+             * * * * * * * * * * Clear OPCODES * * * * * * * * * *
+             */
+            0x18 => {
+                //CLC
+                self.disable_flag(&Flag::Carry);
+            }
+            0xD8 => {
+                //CLD
+                self.disable_flag(&Flag::Dec);
+            }
+            0x58 => {
+                //CLI
+                self.disable_flag(&Flag::IRQ);
+            }
+            0xB8 => {
+                //CLV
+                self.disable_flag(&Flag::Overflow);
+            }
+            /*
+             * * * * * * * * * * CMP OPCODES * * * * * * * * * *
+             */
+            0xC9 => {
+                //CMP-I
+                self.cmp(&AddressingMode::Immediate);
+            }
+            0xC5 => {
+                //CMP-ZP
+                self.cmp(&AddressingMode::ZeroPage);
+            }
+            0xD5 => {
+                //CMP-ZPX
+                self.cmp(&AddressingMode::ZeroPage_X);
+            }
+            0xCD => {
+                //CMP-ABS
+                self.cmp(&AddressingMode::Absolute);
+            }
+            0xDD => {
+                //CMP-ABSX
+                self.cmp(&AddressingMode::Absolute_X);
+            }
+            0xD9 => {
+                //CMP-ABSY
+                self.cmp(&AddressingMode::Absolute_Y);
+            }
+            0xC1 => {
+                //CMP-INDX
+                self.cmp(&AddressingMode::Indirect_X);
+            }
+            0xD1 => {
+                //CMP-INDY
+                self.cmp(&AddressingMode::Indirect_Y);
+            }
+            /*
+             * * * * * * * * * * CPX OPCODES * * * * * * * * * *
+             */
+            0xE0 => {
+                //CPX-I
+                self.cpx(&AddressingMode::Immediate);
+            }
+            0xE4 => {
+                //CPX-ZP
+                self.cpx(&AddressingMode::ZeroPage);
+            }
+            0xEC => {
+                //CPX-ABS
+                self.cpx(&AddressingMode::Absolute);
+            }
+            /*
+             * * * * * * * * * * CPY OPCODES * * * * * * * * * *
+             */
+            0xC0 => {
+                //CPY-I
+                self.cpy(&AddressingMode::Immediate);
+            }
+            0xC4 => {
+                //CPY-ZP
+                self.cpy(&AddressingMode::ZeroPage);
+            }
+            0xCC => {
+                //CPY-ABS
+                self.cpy(&AddressingMode::Absolute);
+            }
+            /*
+             * * * * * * * * * * DEC/DEX/DEY OPCODES * * * * * * * * * *
+             */
+            0xC6 => {
+                //DEC-ZP
+                self.dec(&AddressingMode::ZeroPage);
+            }
+            0xD6 => {
+                //DEC-ZPX
+                self.dec(&AddressingMode::ZeroPage_X);
+            }
+            0xCE => {
+                //DEC-ABS
+                self.dec(&AddressingMode::Absolute);
+            }
+            0xDE => {
+                //DEC-ABSX
+                self.dec(&AddressingMode::Absolute_X);
+            }
+            0xCA => {
+                //DEX
+                self.dex();
+            }
+            0x88 => {
+                //DEY
+                self.dey();
+            }
+            /*
+             * * * * * * * * * * EOR OPCODES * * * * * * * * * *
+             */
+            0x49 => {
+                //EOR-I
+                self.eor(&AddressingMode::Immediate);
+            }
+            0x45 => {
+                //EOR-ZP
+                self.eor(&AddressingMode::ZeroPage);
+            }
+            0x55 => {
+                //EOR-ZPX
+                self.eor(&AddressingMode::ZeroPage_X);
+            }
+            0x4D => {
+                //EOR-ABS
+                self.eor(&AddressingMode::Absolute);
+            }
+            0x5D => {
+                //EOR-ABSX
+                self.eor(&AddressingMode::Absolute_X);
+            }
+            0x59 => {
+                //EOR-ABSY
+                self.eor(&AddressingMode::Absolute_Y);
+            }
+            0x41 => {
+                //EOR-INDX
+                self.eor(&AddressingMode::Indirect_X);
+            }
+            0x51 => {
+                //EOR-INDY
+                self.eor(&AddressingMode::Indirect_Y);
+            }
+            /*
+             * * * * * * * * * * INC OPCODES * * * * * * * * * *
+             */
+            0xE6 => {
+                //INC-ZP
+                self.inc(&AddressingMode::ZeroPage);
+            }
+            0xF6 => {
+                //INC-ZPX
+                self.inc(&AddressingMode::ZeroPage_X);
+            }
+            0xEE => {
+                //INC-ABS
+                self.inc(&AddressingMode::Absolute);
+            }
+            0xFE => {
+                //INC-ABSX
+                self.inc(&AddressingMode::Absolute_X);
+            }
+            0xE8 => {
+                //INX
+                self.inx();
+            }
+            0xC8 => {
+                //INY
+                self.iny();
+            }
+            /*
+             * * * * * * * * * * JMP/RTS OPCODES * * * * * * * * * *
+             */
+            0x4C => {
+                //JMP-ABS
+                let addr = self.get_operand_addressing_mode(&AddressingMode::Absolute);
+                self.program_counter = addr.0;
+            }
+            0x6C => {
+                //JMP-IND
+                let addr = self.mem_read_u16(self.program_counter);
+                let indirect_addr = if addr & 0x00FF == 0x00FF {
+                    let lo = self.mem_read(addr) as u16;
+                    let hi = self.mem_read(addr & 0xFF00) as u16;
+                    (hi << 8) | lo
+                } else {
+                    self.mem_read_u16(addr)
+                };
+                self.program_counter = indirect_addr;
+            }
+            0x20 => {
+                //JSR-ABS
+                let addr = self.get_operand_addressing_mode(&AddressingMode::Absolute);
+                self.stack_push_u16(self.program_counter + 1); //+ 2 - 1
+                self.program_counter = addr.0;
+            }
+            0x60 => {
+                //RTS
+                self.program_counter = self.stack_pop_u16() + 1;
+            }
+            /*
+             * * * * * * * * * * LDA OPCODES * * * * * * * * * *
+             */
+            0xA9 => {
+                //LDA-I
+                self.lda(&AddressingMode::Immediate);
+            }
+            0xA5 => {
+                //LDA-ZP
+                self.lda(&AddressingMode::ZeroPage);
+            }
+            0xB5 => {
+                //LDA-ZPX
+                self.lda(&AddressingMode::ZeroPage_X);
+            }
+            0xAD => {
+                //LDA-ABS
+                self.lda(&AddressingMode::Absolute);
+            }
+            0xBD => {
+                //LDA-ABSX
+                self.lda(&AddressingMode::Absolute_X);
+            }
+            0xB9 => {
+                //LDA-ABSY
+                self.lda(&AddressingMode::Absolute_Y);
+            }
+            0xA1 => {
+                //LDA-INDX
+                self.lda(&AddressingMode::Indirect_X);
+            }
+            0xB1 => {
+                //LDA-INDY
+                self.lda(&AddressingMode::Indirect_Y);
+            }
+            /*
+             * * * * * * * * * * LDX OPCODES * * * * * * * * * *
+             */
+            0xA2 => {
+                //LDX-I
+                self.ldx(&AddressingMode::Immediate);
+            }
+            0xA6 => {
+                //LDX-ZP
+                self.ldx(&AddressingMode::ZeroPage);
+            }
+            0xB6 => {
+                //LDX-ZPY
+                self.ldx(&AddressingMode::ZeroPage_Y);
+            }
+            0xAE => {
+                //LDX-ABS
+                self.ldx(&AddressingMode::Absolute);
+            }
+            0xBE => {
+                //LDX-ABSY
+                self.ldx(&AddressingMode::Absolute_Y);
+            }
+            /*
+             * * * * * * * * * * LDY OPCODES * * * * * * * * * *
+             */
+            0xA0 => {
+                //LDY-I
+                self.ldy(&AddressingMode::Immediate);
+            }
+            0xA4 => {
+                //LDY-ZP
+                self.ldy(&AddressingMode::ZeroPage);
+            }
+            0xB4 => {
+                //LDY-ZPX
+                self.ldy(&AddressingMode::ZeroPage_X);
+            }
+            0xAC => {
+                //LDY-ABS
+                self.ldy(&AddressingMode::Absolute);
+            }
+            0xBC => {
+                //LDY-ABSX
+                self.ldy(&AddressingMode::Absolute_X);
+            }
+            /*
+             * * * * * * * * * * LSR OPCODES * * * * * * * * * *
+             */
+            0x4A => {
+                //LSR-ACC
+                self.lsr_accumulator();
+            }
+            0x46 => {
+                //LSR-ZP
+                self.lsr(&AddressingMode::ZeroPage);
+            }
+            0x56 => {
+                //LSR-ZPX
+                self.lsr(&AddressingMode::ZeroPage_X);
+            }
+            0x4E => {
+                //LSR-ABS
+                self.lsr(&AddressingMode::Absolute);
+            }
+            0x5E => {
+                //LSR-ABSX
+                self.lsr(&AddressingMode::Absolute_X);
+            }
+            /*
+             * * * * * * * * * * ORA OPCODES * * * * * * * * * *
+             */
+            0x09 => {
+                //ORA-I
+                self.ora(&AddressingMode::Immediate);
+            }
+            0x05 => {
+                //ORA-ZP
+                self.ora(&AddressingMode::ZeroPage);
+            }
+            0x15 => {
+                //ORA-ZPX
+                self.ora(&AddressingMode::ZeroPage_X);
+            }
+            0x0D => {
+                //ORA-ABS
+                self.ora(&AddressingMode::Absolute);
+            }
+            0x1D => {
+                //ORA-ABSX
+                self.ora(&AddressingMode::Absolute_X);
+            }
+            0x19 => {
+                //ORA-ABSY
+                self.ora(&AddressingMode::Absolute_Y);
+            }
+            0x01 => {
+                //ORA-INDX
+                self.ora(&AddressingMode::Indirect_X);
+            }
+            0x11 => {
+                //ORA-INDY
+                self.ora(&AddressingMode::Indirect_Y);
+            }
+            /*
+             * * * * * * * * * * PUSH/PULL OPCODES * * * * * * * * * *
+             */
+            0x48 => {
+                //PHA
+                self.stack_push(self.register_a);
+            }
+            0x08 => {
+                //PHP https://www.nesdev.org/wiki/Status_flags#The_B_flag
+                let mut flag = self.status;
+                flag = flag | 0b0011_0000; //enable "B" flag as per wiki
+                self.stack_push(flag);
+            }
+            0x68 => {
+                //PLA
+                self.register_a = self.stack_pop();
+                self.set_zn_flags_v1(self.register_a);
+            }
+            0x28 => {
+                //PLP
+                self.status = self.stack_pop();
+                self.disable_flag(&Flag::Break);
+                self.enable_flag(&Flag::Break2)
+            }
+            /*
+             * * * * * * * * * * ROL OPCODES * * * * * * * * * *
+             */
+            0x2A => {
+                //ROL-ACC
+                self.rol_accumulator();
+            }
+            0x26 => {
+                //ROL-ZP
+                self.rol(&AddressingMode::ZeroPage);
+            }
+            0x36 => {
+                //ROL-ZPX
+                self.rol(&AddressingMode::ZeroPage_X);
+            }
+            0x2E => {
+                //ROL-ABS
+                self.rol(&AddressingMode::Absolute);
+            }
+            0x3E => {
+                //ROL-ABSX
+                self.rol(&AddressingMode::Absolute_X);
+            }
+            /*
+             * * * * * * * * * * ROR OPCODES * * * * * * * * * *
+             */
+            0x6A => {
+                //ROR-ACC
+                self.ror_accumulator();
+            }
+            0x66 => {
+                //ROR-ZP
+                self.ror(&AddressingMode::ZeroPage);
+            }
+            0x76 => {
+                //ROR-ZPX
+                self.ror(&AddressingMode::ZeroPage_X);
+            }
+            0x6E => {
+                //ROR-ABS
+                self.ror(&AddressingMode::Absolute);
+            }
+            0x7E => {
+                //ROR-ABSX
+                self.ror(&AddressingMode::Absolute_X);
+            }
+            /*
+             * * * * * * * * * * RTI/BRK OPCODES * * * * * * * * * *
+             */
+            0x00 => {
+                //BRK, skips the padding byte after the opcode and pushes status with B set
+                self.stack_push_u16(self.program_counter.wrapping_add(1));
+                self.stack_push(self.status | 0b0011_0000);
+                self.enable_flag(&Flag::IRQ);
+                self.program_counter = self.mem_read_u16(0xfffe);
+            }
+            0x40 => {
+                //RTI
+                self.status = self.stack_pop();
+                self.disable_flag(&Flag::Break);
+                self.enable_flag(&Flag::Break2);
+                self.program_counter = self.stack_pop_u16();
+            }
+            /*
+             * * * * * * * * * * SBC OPCODES * * * * * * * * * *
+             */
+            0xE9 => {
+                //SBC-I
+                self.sbc(&AddressingMode::Immediate);
+            }
+            0xE5 => {
+                //SBC-ZP
+                self.sbc(&AddressingMode::ZeroPage);
+            }
+            0xF5 => {
+                //SBC-ZPX
+                self.sbc(&AddressingMode::ZeroPage_X);
+            }
+            0xED => {
+                //SBC-ABS
+                self.sbc(&AddressingMode::Absolute);
+            }
+            0xFD => {
+                //SBC-ABSX
+                self.sbc(&AddressingMode::Absolute_X);
+            }
+            0xF9 => {
+                //SBC-ABSY
+                self.sbc(&AddressingMode::Absolute_Y);
+            }
+            0xE1 => {
+                //SBC-INDX
+                self.sbc(&AddressingMode::Indirect_X);
+            }
+            0xF1 => {
+                //SBC-INDY
+                self.sbc(&AddressingMode::Indirect_Y);
+            }
+            /*
+             * * * * * * * * * * SET OPCODES * * * * * * * * * *
+             */
+            0x38 => {
+                //SEC
+                self.enable_flag(&Flag::Carry);
+            }
+            0xF8 => {
+                //SED
+                self.enable_flag(&Flag::Dec);
+            }
+            0x78 => {
+                //SEI
+                self.enable_flag(&Flag::IRQ);
+            }
+            /*
+             * * * * * * * * * * STA OPCODES * * * * * * * * * *
+             */
+            0x85 => {
+                //STA-ZP
+                self.sta(&AddressingMode::ZeroPage);
+            }
+            0x95 => {
+                //STA-ZPX
+                self.sta(&AddressingMode::ZeroPage_X);
+            }
+            0x8D => {
+                //STA-ABS
+                self.sta(&AddressingMode::Absolute);
+            }
+            0x9D => {
+                //STA-ABSX
+                self.sta(&AddressingMode::Absolute_X);
+            }
+            0x99 => {
+                //STA-ABSY
+                self.sta(&AddressingMode::Absolute_Y);
+            }
+            0x81 => {
+                //STA-INDX
+                self.sta(&AddressingMode::Indirect_X);
+            }
+            0x91 => {
+                //STA-INDY
+                self.sta(&AddressingMode::Indirect_Y);
+            }
+            /*
+             * * * * * * * * * * STX OPCODES * * * * * * * * * *
+             */
+            0x86 => {
+                //STX-ZP
+                self.stx(&AddressingMode::ZeroPage);
+            }
+            0x96 => {
+                //STX-ZPY
+                self.stx(&AddressingMode::ZeroPage_Y);
+            }
+            0x8E => {
+                //STX-ABS
+                self.stx(&AddressingMode::Absolute);
+            }
+            /*
+             * * * * * * * * * * STY OPCODES * * * * * * * * * *
+             */
+            0x84 => {
+                //STY-ZP
+                self.sty(&AddressingMode::ZeroPage);
+            }
+            0x94 => {
+                //STY-ZPX
+                self.sty(&AddressingMode::ZeroPage_X);
+            }
+            0x8C => {
+                //STY-ABS
+                self.sty(&AddressingMode::Absolute);
+            }
+            /*
+             * * * * * * * * * * Transfer OPCODES * * * * * * * * * *
+             */
+            0xAA => {
+                //TAX
+                self.tax();
+            }
+            0xA8 => {
+                //TAY
+                self.register_y = self.register_a;
+                self.set_zn_flags_v1(self.register_y);
+            }
+            0xBA => {
+                //TSX
+                self.register_x = self.stack_ptr;
+                self.set_zn_flags_v1(self.register_x);
+            }
+            0x8A => {
+                //TXA
+                self.register_a = self.register_x;
+                self.set_zn_flags_v1(self.register_a);
+            }
+            0x9A => {
+                //TXS
+                self.stack_ptr = self.register_x;
+            }
+            0x98 => {
+                //TYA
+                self.register_a = self.register_y;
+                self.set_zn_flags_v1(self.register_a);
+            }
+            0xEA | 0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => {
+                //nop
+            }
+            /*
+             * * * * * * * * * * Unofficial OPCODES * * * * * * * * * *
+             */
+            0x4B => {
+                //ALR
+                self.register_a = self.register_a & self.mem_read(self.program_counter);
+                self.lsr_accumulator();
+            }
+            0x0B | 0x2B => {
+                //ANC
+                let result = self.register_a & self.mem_read(self.program_counter);
+                if self.is_negative(result) {
+                    self.enable_flag(&Flag::Carry);
+                } else {
                     self.disable_flag(&Flag::Carry);
                 }
-                0xD8 => {
-                    //CLD
-                    self.disable_flag(&Flag::Dec);
-                }
-                0x58 => {
-                    //CLI
-                    self.disable_flag(&Flag::IRQ);
+                self.register_a = result;
+                self.set_zn_flags_v1(self.register_a);
+            }
+            0x6B => {
+                //ARR
+                self.register_a = self.register_a & self.mem_read(self.program_counter);
+                self.ror_accumulator();
+                let b5 = self.register_a & 0b0010_0000 != 0;
+                let b6 = self.register_a & 0b0100_0000 != 0;
+                if b6 {
+                    self.enable_flag(&Flag::Carry);
+                } else {
+                    self.disable_flag(&Flag::Carry);
                 }
-                0xB8 => {
-                    //CLV
+                if b6 ^ b5 {
+                    self.enable_flag(&Flag::Overflow);
+                } else {
                     self.disable_flag(&Flag::Overflow);
                 }
-                /*
-                 * * * * * * * * * * CMP OPCODES * * * * * * * * * *
-                 */
-                0xC9 => {
-                    //CMP-I
-                    self.cmp(&AddressingMode::Immediate);
-                }
-                0xC5 => {
-                    //CMP-ZP
-                    self.cmp(&AddressingMode::ZeroPage);
-                }
-                0xD5 => {
-                    //CMP-ZPX
-                    self.cmp(&AddressingMode::ZeroPage_X);
-                }
-                0xCD => {
-                    //CMP-ABS
-                    self.cmp(&AddressingMode::Absolute);
-                }
-                0xDD => {
-                    //CMP-ABSX
-                    self.cmp(&AddressingMode::Absolute_X);
-                }
-                0xD9 => {
-                    //CMP-ABSY
-                    self.cmp(&AddressingMode::Absolute_Y);
-                }
-                0xC1 => {
-                    //CMP-INDX
-                    self.cmp(&AddressingMode::Indirect_X);
-                }
-                0xD1 => {
-                    //CMP-INDY
-                    self.cmp(&AddressingMode::Indirect_Y);
-                }
-                /*
-                 * * * * * * * * * * CPX OPCODES * * * * * * * * * *
-                 */
-                0xE0 => {
-                    //CPX-I
-                    self.cpx(&AddressingMode::Immediate);
-                }
-                0xE4 => {
-                    //CPX-ZP
-                    self.cpx(&AddressingMode::ZeroPage);
-                }
-                0xEC => {
-                    //CPX-ABS
-                    self.cpx(&AddressingMode::Absolute);
-                }
-                /*
-                 * * * * * * * * * * CPY OPCODES * * * * * * * * * *
-                 */
-                0xC0 => {
-                    //CPY-I
-                    self.cpy(&AddressingMode::Immediate);
-                }
-                0xC4 => {
-                    //CPY-ZP
-                    self.cpy(&AddressingMode::ZeroPage);
-                }
-                0xCC => {
-                    //CPY-ABS
-                    self.cpy(&AddressingMode::Absolute);
-                }
-                /*
-                 * * * * * * * * * * DEC/DEX/DEY OPCODES * * * * * * * * * *
-                 */
-                0xC6 => {
-                    //DEC-ZP
-                    self.dec(&AddressingMode::ZeroPage);
-                }
-                0xD6 => {
-                    //DEC-ZPX
-                    self.dec(&AddressingMode::ZeroPage_X);
-                }
-                0xCE => {
-                    //DEC-ABS
-                    self.dec(&AddressingMode::Absolute);
-                }
-                0xDE => {
-                    //DEC-ABSX
-                    self.dec(&AddressingMode::Absolute_X);
-                }
-                0xCA => {
-                    //DEX
-                    self.dex();
-                }
-                0x88 => {
-                    //DEY
-                    self.dey();
-                }
-                /*
-                 * * * * * * * * * * EOR OPCODES * * * * * * * * * *
-                 */
-                0x49 => {
-                    //EOR-I
-                    self.eor(&AddressingMode::Immediate);
-                }
-                0x45 => {
-                    //EOR-ZP
-                    self.eor(&AddressingMode::ZeroPage);
-                }
-                0x55 => {
-                    //EOR-ZPX
-                    self.eor(&AddressingMode::ZeroPage_X);
-                }
-                0x4D => {
-                    //EOR-ABS
-                    self.eor(&AddressingMode::Absolute);
-                }
-                0x5D => {
-                    //EOR-ABSX
-                    self.eor(&AddressingMode::Absolute_X);
-                }
-                0x59 => {
-                    //EOR-ABSY
-                    self.eor(&AddressingMode::Absolute_Y);
-                }
-                0x41 => {
-                    //EOR-INDX
-                    self.eor(&AddressingMode::Indirect_X);
-                }
-                0x51 => {
-                    //EOR-INDY
-                    self.eor(&AddressingMode::Indirect_Y);
-                }
-                /*
-                 * * * * * * * * * * INC OPCODES * * * * * * * * * *
-                 */
-                0xE6 => {
-                    //INC-ZP
-                    self.inc(&AddressingMode::ZeroPage);
-                }
-                0xF6 => {
-                    //INC-ZPX
-                    self.inc(&AddressingMode::ZeroPage_X);
-                }
-                0xEE => {
-                    //INC-ABS
-                    self.inc(&AddressingMode::Absolute);
-                }
-                0xFE => {
-                    //INC-ABSX
-                    self.inc(&AddressingMode::Absolute_X);
-                }
-                0xE8 => {
-                    //INX
-                    self.inx();
-                }
-                0xC8 => {
-                    //INY
-                    self.iny();
-                }
-                /*
-                 * * * * * * * * * * JMP/RTS OPCODES * * * * * * * * * *
-                 */
-                0x4C => {
-                    //JMP-ABS
-                    let addr = self.get_operand_addressing_mode(&AddressingMode::Absolute);
-                    self.program_counter = addr.0;
-                }
-                0x6C => {
-                    //JMP-IND
-                    let addr = self.mem_read_u16(self.program_counter);
-                    let indirect_addr = if addr & 0x00FF == 0x00FF {
-                        let lo = self.mem_read(addr) as u16;
-                        let hi = self.mem_read(addr & 0xFF00) as u16;
-                        (hi << 8) | lo
-                    } else {
-                        self.mem_read_u16(addr)
-                    };
-                    self.program_counter = indirect_addr;
-                }
-                0x20 => {
-                    //JSR-ABS
-                    let addr = self.get_operand_addressing_mode(&AddressingMode::Absolute);
-                    self.stack_push_u16(self.program_counter + 1); //+ 2 - 1
-                    self.program_counter = addr.0;
-                }
-                0x60 => {
-                    //RTS
-                    self.program_counter = self.stack_pop_u16() + 1;
-                }
-                /*
-                 * * * * * * * * * * LDA OPCODES * * * * * * * * * *
-                 */
-                0xA9 => {
-                    //LDA-I
-                    self.lda(&AddressingMode::Immediate);
-                }
-                0xA5 => {
-                    //LDA-ZP
-                    self.lda(&AddressingMode::ZeroPage);
-                }
-                0xB5 => {
-                    //LDA-ZPX
-                    self.lda(&AddressingMode::ZeroPage_X);
-                }
-                0xAD => {
-                    //LDA-ABS
-                    self.lda(&AddressingMode::Absolute);
-                }
-                0xBD => {
-                    //LDA-ABSX
-                    self.lda(&AddressingMode::Absolute_X);
-                }
-                0xB9 => {
-                    //LDA-ABSY
-                    self.lda(&AddressingMode::Absolute_Y);
-                }
-                0xA1 => {
-                    //LDA-INDX
-                    self.lda(&AddressingMode::Indirect_X);
-                }
-                0xB1 => {
-                    //LDA-INDY
-                    self.lda(&AddressingMode::Indirect_Y);
-                }
-                /*
-                 * * * * * * * * * * LDX OPCODES * * * * * * * * * *
-                 */
-                0xA2 => {
-                    //LDX-I
-                    self.ldx(&AddressingMode::Immediate);
-                }
-                0xA6 => {
-                    //LDX-ZP
-                    self.ldx(&AddressingMode::ZeroPage);
-                }
-                0xB6 => {
-                    //LDX-ZPY
-                    self.ldx(&AddressingMode::ZeroPage_Y);
-                }
-                0xAE => {
-                    //LDX-ABS
-                    self.ldx(&AddressingMode::Absolute);
-                }
-                0xBE => {
-                    //LDX-ABSY
-                    self.ldx(&AddressingMode::Absolute_Y);
-                }
-                /*
-                 * * * * * * * * * * LDY OPCODES * * * * * * * * * *
-                 */
-                0xA0 => {
-                    //LDY-I
-                    self.ldy(&AddressingMode::Immediate);
-                }
-                0xA4 => {
-                    //LDY-ZP
-                    self.ldy(&AddressingMode::ZeroPage);
-                }
-                0xB4 => {
-                    //LDY-ZPX
-                    self.ldy(&AddressingMode::ZeroPage_X);
-                }
-                0xAC => {
-                    //LDY-ABS
-                    self.ldy(&AddressingMode::Absolute);
-                }
-                0xBC => {
-                    //LDY-ABSX
-                    self.ldy(&AddressingMode::Absolute_X);
-                }
-                /*
-                 * * * * * * * * * * LSR OPCODES * * * * * * * * * *
-                 */
-                0x4A => {
-                    //LSR-ACC
-                    self.lsr_accumulator();
-                }
-                0x46 => {
-                    //LSR-ZP
-                    self.lsr(&AddressingMode::ZeroPage);
-                }
-                0x56 => {
-                    //LSR-ZPX
-                    self.lsr(&AddressingMode::ZeroPage_X);
-                }
-                0x4E => {
-                    //LSR-ABS
-                    self.lsr(&AddressingMode::Absolute);
-                }
-                0x5E => {
-                    //LSR-ABSX
-                    self.lsr(&AddressingMode::Absolute_X);
-                }
-                /*
-                 * * * * * * * * * * ORA OPCODES * * * * * * * * * *
-                 */
-                0x09 => {
-                    //ORA-I
-                    self.ora(&AddressingMode::Immediate);
-                }
-                0x05 => {
-                    //ORA-ZP
-                    self.ora(&AddressingMode::ZeroPage);
-                }
-                0x15 => {
-                    //ORA-ZPX
-                    self.ora(&AddressingMode::ZeroPage_X);
-                }
-                0x0D => {
-                    //ORA-ABS
-                    self.ora(&AddressingMode::Absolute);
-                }
-                0x1D => {
-                    //ORA-ABSX
-                    self.ora(&AddressingMode::Absolute_X);
-                }
-                0x19 => {
-                    //ORA-ABSY
-                    self.ora(&AddressingMode::Absolute_Y);
-                }
-                0x01 => {
-                    //ORA-INDX
-                    self.ora(&AddressingMode::Indirect_X);
-                }
-                0x11 => {
-                    //ORA-INDY
-                    self.ora(&AddressingMode::Indirect_Y);
-                }
-                /*
-                 * * * * * * * * * * PUSH/PULL OPCODES * * * * * * * * * *
-                 */
-                0x48 => {
-                    //PHA
-                    self.stack_push(self.register_a);
-                }
-                0x08 => {
-                    //PHP https://www.nesdev.org/wiki/Status_flags#The_B_flag
-                    let mut flag = self.status;
-                    flag = flag | 0b0011_0000; //enable "B" flag as per wiki
-                    self.stack_push(flag);
-                }
-                0x68 => {
-                    //PLA
-                    self.register_a = self.stack_pop();
-                    self.set_zn_flags_v1(self.register_a);
-                }
-                0x28 => {
-                    //PLP
-                    self.status = self.stack_pop();
-                    self.disable_flag(&Flag::Break);
-                    self.enable_flag(&Flag::Break2)
-                }
-                /*
-                 * * * * * * * * * * ROL OPCODES * * * * * * * * * *
-                 */
-                0x2A => {
-                    //ROL-ACC
-                    self.rol_accumulator();
-                }
-                0x26 => {
-                    //ROL-ZP
-                    self.rol(&AddressingMode::ZeroPage);
-                }
-                0x36 => {
-                    //ROL-ZPX
-                    self.rol(&AddressingMode::ZeroPage_X);
-                }
-                0x2E => {
-                    //ROL-ABS
-                    self.rol(&AddressingMode::Absolute);
-                }
-                0x3E => {
-                    //ROL-ABSX
-                    self.rol(&AddressingMode::Absolute_X);
-                }
-                /*
-                 * * * * * * * * * * ROR OPCODES * * * * * * * * * *
-                 */
-                0x6A => {
-                    //ROR-ACC
-                    self.ror_accumulator();
-                }
-                0x66 => {
-                    //ROR-ZP
-                    self.ror(&AddressingMode::ZeroPage);
-                }
-                0x76 => {
-                    //ROR-ZPX
-                    self.ror(&AddressingMode::ZeroPage_X);
-                }
-                0x6E => {
-                    //ROR-ABS
-                    self.ror(&AddressingMode::Absolute);
-                }
-                0x7E => {
-                    //ROR-ABSX
-                    self.ror(&AddressingMode::Absolute_X);
-                }
-                /*
-                 * * * * * * * * * * RTI/BRK OPCODES * * * * * * * * * *
-                 */
-                0x00 => {
-                    //brk
-                    /*let mut flag = self.status;
-                    flag = flag | 0b0011_0000; //enable "B" flag as per wiki
-                    self.stack_push_u16(self.program_counter);
-                    self.stack_push(flag);
-                    self.program_counter = self.mem_read_u16(0xFFFE);
-                    self.enable_flag(&Flag::Break);*/
-                    return;
-                }
-                0x40 => {
-                    //RTI
-                    self.status = self.stack_pop();
-                    self.disable_flag(&Flag::Break);
-                    self.enable_flag(&Flag::Break2);
-                    self.program_counter = self.stack_pop_u16();
-                }
-                /*
-                 * * * * * * * * * * SBC OPCODES * * * * * * * * * *
-                 */
-                0xE9 => {
-                    //SBC-I
-                    self.sbc(&AddressingMode::Immediate);
-                }
-                0xE5 => {
-                    //SBC-ZP
-                    self.sbc(&AddressingMode::ZeroPage);
-                }
-                0xF5 => {
-                    //SBC-ZPX
-                    self.sbc(&AddressingMode::ZeroPage_X);
-                }
-                0xED => {
-                    //SBC-ABS
-                    self.sbc(&AddressingMode::Absolute);
-                }
-                0xFD => {
-                    //SBC-ABSX
-                    self.sbc(&AddressingMode::Absolute_X);
-                }
-                0xF9 => {
-                    //SBC-ABSY
-                    self.sbc(&AddressingMode::Absolute_Y);
-                }
-                0xE1 => {
-                    //SBC-INDX
-                    self.sbc(&AddressingMode::Indirect_X);
-                }
-                0xF1 => {
-                    //SBC-INDY
-                    self.sbc(&AddressingMode::Indirect_Y);
-                }
-                /*
-                 * * * * * * * * * * SET OPCODES * * * * * * * * * *
-                 */
-                0x38 => {
-                    //SEC
+            }
+            0xCB => {
+                //AXS
+                let operand = self.mem_read(self.program_counter);
+                let andresult = self.register_a & self.register_x;
+                let result = andresult.wrapping_sub(operand);
+                if operand <= andresult {
                     self.enable_flag(&Flag::Carry);
                 }
-                0xF8 => {
-                    //SED
-                    self.enable_flag(&Flag::Dec);
-                }
-                0x78 => {
-                    //SEI
-                    self.enable_flag(&Flag::IRQ);
-                }
-                /*
-                 * * * * * * * * * * STA OPCODES * * * * * * * * * *
-                 */
-                0x85 => {
-                    //STA-ZP
-                    self.sta(&AddressingMode::ZeroPage);
-                }
-                0x95 => {
-                    //STA-ZPX
-                    self.sta(&AddressingMode::ZeroPage_X);
-                }
-                0x8D => {
-                    //STA-ABS
-                    self.sta(&AddressingMode::Absolute);
-                }
-                0x9D => {
-                    //STA-ABSX
-                    self.sta(&AddressingMode::Absolute_X);
-                }
-                0x99 => {
-                    //STA-ABSY
-                    self.sta(&AddressingMode::Absolute_Y);
-                }
-                0x81 => {
-                    //STA-INDX
-                    self.sta(&AddressingMode::Indirect_X);
-                }
-                0x91 => {
-                    //STA-INDY
-                    self.sta(&AddressingMode::Indirect_Y);
-                }
-                /*
-                 * * * * * * * * * * STX OPCODES * * * * * * * * * *
-                 */
-                0x86 => {
-                    //STX-ZP
-                    self.stx(&AddressingMode::ZeroPage);
-                }
-                0x96 => {
-                    //STX-ZPY
-                    self.stx(&AddressingMode::ZeroPage_Y);
-                }
-                0x8E => {
-                    //STX-ABS
-                    self.stx(&AddressingMode::Absolute);
-                }
-                /*
-                 * * * * * * * * * * STY OPCODES * * * * * * * * * *
-                 */
-                0x84 => {
-                    //STY-ZP
-                    self.sty(&AddressingMode::ZeroPage);
-                }
-                0x94 => {
-                    //STY-ZPX
-                    self.sty(&AddressingMode::ZeroPage_X);
-                }
-                0x8C => {
-                    //STY-ABS
-                    self.sty(&AddressingMode::Absolute);
-                }
-                /*
-                 * * * * * * * * * * Transfer OPCODES * * * * * * * * * *
-                 */
-                0xAA => {
-                    //TAX
-                    self.tax();
-                }
-                0xA8 => {
-                    //TAY
-                    self.register_y = self.register_a;
-                    self.set_zn_flags_v1(self.register_y);
-                }
-                0xBA => {
-                    //TSX
-                    self.register_x = self.stack_ptr;
-                    self.set_zn_flags_v1(self.register_x);
-                }
-                0x8A => {
-                    //TXA
-                    self.register_a = self.register_x;
-                    self.set_zn_flags_v1(self.register_a);
-                }
-                0x9A => {
-                    //TXS
-                    self.stack_ptr = self.register_x;
-                }
-                0x98 => {
-                    //TYA
-                    self.register_a = self.register_y;
-                    self.set_zn_flags_v1(self.register_a);
-                }
-                0xEA | 0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => {
-                    //nop
-                }
-                /*
-                 * * * * * * * * * * Unofficial OPCODES * * * * * * * * * *
-                 */
-                0x4B => {
-                    //ALR
-                    self.register_a = self.register_a & self.mem_read(self.program_counter);
-                    self.lsr_accumulator();
-                }
-                0x0B | 0x2B => {
-                    //ANC
-                    let result = self.register_a & self.mem_read(self.program_counter);
-                    if self.is_negative(result) {
-                        self.enable_flag(&Flag::Carry);
-                    } else {
-                        self.disable_flag(&Flag::Carry);
-                    }
-                    self.register_a = result;
-                    self.set_zn_flags_v1(self.register_a);
-                }
-                0x6B => {
-                    //ARR
-                    self.register_a = self.register_a & self.mem_read(self.program_counter);
-                    self.ror_accumulator();
-                    let b5 = self.register_a & 0b0010_0000 != 0;
-                    let b6 = self.register_a & 0b0100_0000 != 0;
-                    if b6 {
-                        self.enable_flag(&Flag::Carry);
-                    } else {
-                        self.disable_flag(&Flag::Carry);
-                    }
-                    if b6 ^ b5 {
-                        self.enable_flag(&Flag::Overflow);
-                    } else {
-                        self.disable_flag(&Flag::Overflow);
-                    }
-                }
-                0xCB => {
-                    //AXS
-                    let operand = self.mem_read(self.program_counter);
-                    let andresult = self.register_a & self.register_x;
-                    let result = andresult.wrapping_sub(operand);
-                    if operand <= andresult {
-                        self.enable_flag(&Flag::Carry);
-                    }
-                    self.set_zn_flags_v1(result);
-                    self.register_x = result;
-                }
-                0xC3 | 0xC7 | 0xCF | 0xD3 | 0xD7 | 0xDB | 0xDF => {
-                    let mode = &opscode_data.mode;
-                    let (addr, _) = self.get_operand_addressing_mode(mode);
-                    let mut operand = self.mem_read(addr);
-                    operand = operand.wrapping_sub(1);
-                    self.mem_write(addr, operand);
-                    self.set_zn_flags_v1(operand);
-                    let (addr, _) = self.get_operand_addressing_mode(mode);
-                    let operand = self.mem_read(addr);
-                    if self.register_a >= operand {
-                        self.enable_flag(&Flag::Carry);
-                    } else {
-                        self.disable_flag(&Flag::Carry);
-                    }
-                    self.set_zn_flags_v1(self.register_a.wrapping_sub(operand));
-                    //self.dec(mode);
-                    //self.cmp(mode);
-                }
-                0x0C | 0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC | 0x04 | 0x44 | 0x64 | 0x14
-                | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => {
-                    //IGN
-                    let (addr, page_cross) = self.get_operand_addressing_mode(&opscode_data.mode);
-                    self.mem_read(addr);
-                    if page_cross {
-                        self.bus.tick(1);
-                    }
-                }
-                0xE3 | 0xE7 | 0xEF | 0xF3 | 0xF7 | 0xFB | 0xFF => {
-                    //ISC/ISB
-                    let mode = &opscode_data.mode;
-                    self.inc(mode);
-                    let (addr, _) = self.get_operand_addressing_mode(mode);
-                    let operand = self.mem_read(addr);
-                    self.add(operand.wrapping_neg().wrapping_sub(1));
-                    self.set_zn_flags_v1(self.register_a);
-                    //self.sbc(mode);
-                }
-                0xA3 | 0xA7 | 0xAF | 0xB3 | 0xB7 | 0xBF => {
-                    //LAX
-                    self.lda(&opscode_data.mode);
-                    self.tax();
-                }
-                0xAB => {
-                    //LXA
-                    self.register_a = self.register_a & self.mem_read(self.program_counter);
-                    self.set_zn_flags_v1(self.register_a);
-                    self.tax();
-                }
-                0x23 | 0x27 | 0x2F | 0x33 | 0x37 | 0x3B | 0x3F => {
-                    //RLA
-                    let mode = &opscode_data.mode;
-                    self.rol(mode);
-                    let (addr, _) = self.get_operand_addressing_mode(mode);
-                    self.register_a = self.register_a & self.mem_read(addr);
-                    self.set_zn_flags_v1(self.register_a);
-                    //self.and(mode);
-                }
-                0x63 | 0x67 | 0x6F | 0x73 | 0x77 | 0x7B | 0x7F => {
-                    //RRA
-                    let mode = &opscode_data.mode;
-                    self.ror(mode);
-                    let (addr, _) = self.get_operand_addressing_mode(mode);
-                    let d = self.mem_read(addr);
-                    self.add(d);
-                    self.set_zn_flags_v1(self.register_a);
-                    //self.adc(mode);
-                }
-                0x87 | 0x97 | 0x83 | 0x8F => {
-                    //SAX
-                    self.sax(&opscode_data.mode);
-                }
-                0xEB => {
-                    //SBC
-                    let operand = self.mem_read(self.program_counter);
-                    self.add(((operand as i8).wrapping_neg().wrapping_sub(1)) as u8);
-                    self.set_zn_flags_v1(self.register_a);
-                }
-                0x9F | 0x93 => {
-                    //SHA
-                }
-                0x03 | 0x07 | 0x0F | 0x13 | 0x17 | 0x1B | 0x1F => {
-                    //SLO
-                    let mode = &opscode_data.mode;
-                    self.asl(mode);
-                    let (addr, _) = self.get_operand_addressing_mode(mode);
-                    self.register_a = self.register_a | self.mem_read(addr);
-                    self.set_zn_flags_v1(self.register_a);
-                    //self.ora(mode);
-                }
-                0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => {
-                    //SKB
-                    self.mem_read(self.program_counter);
+                self.set_zn_flags_v1(result);
+                self.register_x = result;
+            }
+            0xC3 | 0xC7 | 0xCF | 0xD3 | 0xD7 | 0xDB | 0xDF => {
+                let mode = &opscode_data.mode;
+                let (addr, _) = self.get_operand_addressing_mode(mode);
+                let mut operand = self.mem_read(addr);
+                operand = operand.wrapping_sub(1);
+                self.mem_write(addr, operand);
+                self.set_zn_flags_v1(operand);
+                let (addr, _) = self.get_operand_addressing_mode(mode);
+                let operand = self.mem_read(addr);
+                if self.register_a >= operand {
+                    self.enable_flag(&Flag::Carry);
+                } else {
+                    self.disable_flag(&Flag::Carry);
                 }
-                0x43 | 0x47 | 0x4F | 0x53 | 0x57 | 0x5B | 0x5F => {
-                    //SRE
-                    let mode = &opscode_data.mode;
-                    self.lsr(mode);
-                    let (addr, _) = self.get_operand_addressing_mode(mode);
-                    self.register_a = self.register_a ^ self.mem_read(addr);
-                    self.set_zn_flags_v1(self.register_a);
-                    //self.eor(mode);
+                self.set_zn_flags_v1(self.register_a.wrapping_sub(operand));
+                //self.dec(mode);
+                //self.cmp(mode);
+            }
+            0x0C | 0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC | 0x04 | 0x44 | 0x64 | 0x14 | 0x34
+            | 0x54 | 0x74 | 0xD4 | 0xF4 => {
+                //IGN
+                let (addr, page_cross) = self.get_operand_addressing_mode(&opscode_data.mode);
+                self.mem_read(addr);
+                if page_cross {
+                    self.bus.tick(1);
                 }
-                _ => panic!(),
             }
-            //println!("opscode cycle: {}", opscode_data.cycles);
-            self.bus.tick(opscode_data.cycles);
-            if pccopy == self.program_counter {
-                self.program_counter += (opscode_data.len - 1) as u16;
+            0xE3 | 0xE7 | 0xEF | 0xF3 | 0xF7 | 0xFB | 0xFF => {
+                //ISC/ISB
+                let mode = &opscode_data.mode;
+                self.inc(mode);
+                let (addr, _) = self.get_operand_addressing_mode(mode);
+                let operand = self.mem_read(addr);
+                self.add(operand.wrapping_neg().wrapping_sub(1));
+                self.set_zn_flags_v1(self.register_a);
+                //self.sbc(mode);
+            }
+            0xA3 | 0xA7 | 0xAF | 0xB3 | 0xB7 | 0xBF => {
+                //LAX
+                self.lda(&opscode_data.mode);
+                self.tax();
+            }
+            0xAB => {
+                //LXA
+                self.register_a = self.register_a & self.mem_read(self.program_counter);
+                self.set_zn_flags_v1(self.register_a);
+                self.tax();
+            }
+            0x23 | 0x27 | 0x2F | 0x33 | 0x37 | 0x3B | 0x3F => {
+                //RLA
+                let mode = &opscode_data.mode;
+                self.rol(mode);
+                let (addr, _) = self.get_operand_addressing_mode(mode);
+                self.register_a = self.register_a & self.mem_read(addr);
+                self.set_zn_flags_v1(self.register_a);
+                //self.and(mode);
+            }
+            0x63 | 0x67 | 0x6F | 0x73 | 0x77 | 0x7B | 0x7F => {
+                //RRA
+                let mode = &opscode_data.mode;
+                self.ror(mode);
+                let (addr, _) = self.get_operand_addressing_mode(mode);
+                let d = self.mem_read(addr);
+                self.add(d);
+                self.set_zn_flags_v1(self.register_a);
+                //self.adc(mode);
+            }
+            0x87 | 0x97 | 0x83 | 0x8F => {
+                //SAX
+                self.sax(&opscode_data.mode);
+            }
+            0xEB => {
+                //SBC
+                let operand = self.mem_read(self.program_counter);
+                self.add(((operand as i8).wrapping_neg().wrapping_sub(1)) as u8);
+                self.set_zn_flags_v1(self.register_a);
+            }
+            0x9F | 0x93 => {
+                //SHA
+            }
+            0x03 | 0x07 | 0x0F | 0x13 | 0x17 | 0x1B | 0x1F => {
+                //SLO
+                let mode = &opscode_data.mode;
+                self.asl(mode);
+                let (addr, _) = self.get_operand_addressing_mode(mode);
+                self.register_a = self.register_a | self.mem_read(addr);
+                self.set_zn_flags_v1(self.register_a);
+                //self.ora(mode);
             }
+            0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => {
+                //SKB
+                self.mem_read(self.program_counter);
+            }
+            0x43 | 0x47 | 0x4F | 0x53 | 0x57 | 0x5B | 0x5F => {
+                //SRE
+                let mode = &opscode_data.mode;
+                self.lsr(mode);
+                let (addr, _) = self.get_operand_addressing_mode(mode);
+                self.register_a = self.register_a ^ self.mem_read(addr);
+                self.set_zn_flags_v1(self.register_a);
+                //self.eor(mode);
+            }
+            _ => panic!(),
+        }
+        //println!("opscode cycle: {}", opscode_data.cycles);
+        self.bus.tick(opscode_data.cycles);
+        if pccopy == self.program_counter {
+            self.program_counter += (opscode_data.len - 1) as u16;
         }
+        //CLI, SEI and PLP change the i flag after the interrupt poll, RTI before it
+        self.irq_inhibit = match opscode {
+            0x58 | 0x78 | 0x28 => irq_flag_before,
+            _ => self.get_flag_status(&Flag::IRQ),
+        };
     }
     fn tax(&mut self) {
         self.register_x = self.register_a;
         self.set_zn_flags_v1(self.register_x);
     }
 }
+impl Snapshot for CPU<'_> {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u8(&mut self.register_a);
+        s.u8(&mut self.register_x);
+        s.u8(&mut self.register_y);
+        s.u8(&mut self.status);
+        s.u8(&mut self.stack_ptr);
+        s.u16(&mut self.program_counter);
+        s.bool(&mut self.irq_inhibit);
+        self.bus.snapshot(s);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Rom;
+    use crate::Console;
+    //NROM image running `program` from $8000, the irq/brk vector points at an RTI at $9000
+    fn console(program: &[u8]) -> Console {
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1];
+        raw.resize(16, 0);
+        let mut prg = vec![0xea; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x1000] = 0x40;
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0x80, 0x00, 0x90]);
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        Console::new(Rom::new(&raw).unwrap()).unwrap()
+    }
+    #[test]
+    fn brk_jumps_through_the_irq_vector() {
+        let mut console = console(&[0x00, 0xff]);
+        console.cpu.status = 0b0000_0001;
+        let stack = console.cpu.stack_ptr;
+        console.step_instruction();
+        let cpu = &mut console.cpu;
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.stack_ptr, stack.wrapping_sub(3));
+        assert!(cpu.get_flag_status(&Flag::IRQ));
+        //status is pushed with B and bit 5 set, the return address skips the padding byte
+        assert_eq!(cpu.stack_pop(), 0b0011_0001);
+        assert_eq!(cpu.stack_pop_u16(), 0x8002);
+    }
+    #[test]
+    fn rti_returns_past_the_brk_padding_byte() {
+        let mut console = console(&[0x00, 0xff, 0xe8]);
+        console.step_instruction();
+        console.step_instruction();
+        assert_eq!(console.cpu.program_counter, 0x8002);
+        console.step_instruction();
+        assert_eq!(console.cpu.register_x, 1);
+    }
+}
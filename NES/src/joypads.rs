@@ -1,3 +1,4 @@
+use crate::savestate::{Snapshot, StateStream};
 pub enum Button {
     Right,
     Left,
@@ -40,9 +41,9 @@ impl Joypad {
     }
     pub fn set_button(&mut self, button: &Button, pressed: bool) {
         if pressed {
-            self.button_status = self.get_button(button);
+            self.button_status = self.button_status | self.get_button(button);
         } else {
-            self.button_status = 0;
+            self.button_status = self.button_status & !self.get_button(button);
         }
     }
     pub fn get_button(&self, button: &Button) -> u8 {
@@ -59,3 +60,10 @@ impl Joypad {
         }
     }
 }
+impl Snapshot for Joypad {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u8(&mut self.button_status);
+        s.bool(&mut self.strobe);
+        s.u8(&mut self.bidx);
+    }
+}
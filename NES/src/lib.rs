@@ -0,0 +1,18 @@
+pub mod apu;
+pub mod audio;
+pub mod battery;
+pub mod bus;
+pub mod console;
+pub mod cpu;
+pub mod joypads;
+pub mod mapper;
+pub mod movie;
+pub mod opcodes;
+pub mod ppu;
+pub mod ppu_utils;
+pub mod render;
+pub mod rewind;
+pub mod rom;
+pub mod savestate;
+pub mod trace;
+pub use console::Console;
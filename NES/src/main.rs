@@ -1,243 +1,70 @@
-pub mod TileViewer;
-pub mod apu;
-pub mod bus;
-pub mod cpu;
-pub mod joypads;
-pub mod opcodes;
-pub mod ppu;
-pub mod ppu_utils;
-pub mod render;
-pub mod rom;
-use bus::Bus;
-use bus::Memory;
-use cpu::AddressingMode;
-use cpu::CPU;
-use joypads::Button;
-use ppu::PPU;
-use render::frame::Frame;
-use rom::Rom;
+use nes::audio::ring_buffer::{ring_buffer, Consumer};
+use nes::battery::SaveFile;
+use nes::joypads::Button;
+use nes::movie::Movie;
+use nes::rewind::Rewind;
+use nes::rom::Rom;
+use nes::Console;
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
-use sdl2::EventPump;
 use std::collections::HashMap;
 use std::env;
+use std::path::Path;
+use std::time::Duration;
 
-fn input_handler(cpu: &mut CPU, event_pump: &mut EventPump) {
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. }
-            | Event::KeyDown {
-                keycode: Some(Keycode::Escape),
-                ..
-            } => std::process::exit(0),
-            Event::KeyDown {
-                keycode: Some(Keycode::W),
-                ..
-            } => {
-                cpu.mem_write(0xff, 0x77);
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::A),
-                ..
-            } => {
-                cpu.mem_write(0xff, 0x61);
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::S),
-                ..
-            } => {
-                cpu.mem_write(0xff, 0x73);
-            }
-            Event::KeyDown {
-                keycode: Some(Keycode::D),
-                ..
-            } => {
-                cpu.mem_write(0xff, 0x64);
-            }
-            _ => { /*do nothing*/ }
-        }
-    }
-}
-fn color(byte: u8) -> Color {
-    match byte {
-        0 => sdl2::pixels::Color::BLACK,
-        1 => sdl2::pixels::Color::WHITE,
-        2 | 9 => sdl2::pixels::Color::GREY,
-        3 | 10 => sdl2::pixels::Color::RED,
-        4 | 11 => sdl2::pixels::Color::GREEN,
-        5 | 12 => sdl2::pixels::Color::BLUE,
-        6 | 13 => sdl2::pixels::Color::MAGENTA,
-        7 | 14 => sdl2::pixels::Color::YELLOW,
-        _ => sdl2::pixels::Color::CYAN,
-    }
+//about 50ms of audio at 44.1kHz, the emulator waits while more than this is queued
+const AUDIO_TARGET_FILL: usize = 2048;
+//battery saves are written every 5 seconds (when they changed) as well as on exit
+const SAVE_FLUSH_FRAMES: u32 = 300;
+//rewind keeps a state every 4 frames with a keyframe every 2 seconds, in at most 64MB
+const REWIND_INTERVAL: u64 = 4;
+const REWIND_GROUP_SIZE: usize = 30;
+const REWIND_BUDGET: usize = 64 * 1024 * 1024;
+
+struct AudioOut {
+    samples: Consumer,
+    last: f32,
 }
-fn read_screen_state(cpu: &mut CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
-    let mut frame_idx = 0;
-    let mut update = false;
-    for i in 0x0200..0x600 {
-        let color_idx = cpu.mem_read(i as u16);
-        let (b1, b2, b3) = color(color_idx).rgb();
-        if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-            frame[frame_idx] = b1;
-            frame[frame_idx + 1] = b2;
-            frame[frame_idx + 2] = b3;
-            update = true;
+impl AudioCallback for AudioOut {
+    type Channel = f32;
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            //on underrun hold the last level instead of snapping to zero
+            self.last = self.samples.pop().unwrap_or(self.last);
+            *sample = self.last;
         }
-        frame_idx += 3;
     }
-    update
 }
-fn trace(cpu: &mut CPU) -> String {
-    let ref opscodes: HashMap<u8, &'static opcodes::Opcode> = *opcodes::OPCODES_MAP;
-    let program_counter = cpu.program_counter;
-    let register_a = cpu.register_a;
-    let register_x = cpu.register_x;
-    let register_y = cpu.register_y;
-    let p = cpu.status;
-    let sp = cpu.stack_ptr;
-    let opscode = cpu.mem_read(program_counter);
-    let opscode_data = opscodes.get(&opscode).unwrap();
-    let registers = format!(
-        "A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
-        register_a, register_x, register_y, p, sp
-    );
-    let ppuSL = cpu.bus.ppu.scanline;
-    if ppuSL == 242 {
-        std::process::exit(0);
-    }
-    let ppucyc = cpu.bus.ppu.cycles;
-    let buscyc = cpu.bus.cycles;
-    let ppuinfo = format!("PPU:{:3},{:3} CYC:{}", ppuSL, ppucyc, buscyc);
-    let mut hex_dump = vec![];
-    hex_dump.push(opscode);
 
-    let (mem_addr, stored_value) = match opscode_data.mode {
-        AddressingMode::Immediate | AddressingMode::NotSupported => (0, 0),
-        _ => {
-            let addr =
-                cpu.get_operand_addressing_mode_trace(&opscode_data.mode, program_counter + 1);
-            (addr, cpu.mem_read(addr))
+fn flush_battery(save_file: &mut Option<SaveFile>, console: &Console) {
+    if let Some(save_file) = save_file {
+        if let Err(e) = save_file.flush(console) {
+            println!("Could not write {}: {}", save_file.path.display(), e);
         }
-    };
-
-    let tmp = match opscode_data.len {
-        1 => match opscode_data.code {
-            0x0a | 0x4a | 0x2a | 0x6a => format!("A "),
-            _ => String::from(""),
-        },
-        2 => {
-            let address: u8 = cpu.mem_read(program_counter + 1);
-            // let value = cpu.mem_read(address));
-            hex_dump.push(address);
-
-            match opscode_data.mode {
-                AddressingMode::Immediate => format!("#${:02x}", address),
-                AddressingMode::ZeroPage => format!("${:02x} = {:02x}", mem_addr, stored_value),
-                AddressingMode::ZeroPage_X => format!(
-                    "${:02x},X @ {:02x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                AddressingMode::ZeroPage_Y => format!(
-                    "${:02x},Y @ {:02x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                AddressingMode::Indirect_X => format!(
-                    "(${:02x},X) @ {:02x} = {:04x} = {:02x}",
-                    address,
-                    (address.wrapping_add(cpu.register_x)),
-                    mem_addr,
-                    stored_value
-                ),
-                AddressingMode::Indirect_Y => format!(
-                    "(${:02x}),Y = {:04x} @ {:04x} = {:02x}",
-                    address,
-                    (mem_addr.wrapping_sub(cpu.register_y as u16)),
-                    mem_addr,
-                    stored_value
-                ),
-                AddressingMode::NotSupported => {
-                    // assuming local jumps: BNE, BVS, etc....
-                    let address: usize =
-                        (program_counter as usize + 2).wrapping_add((address as i8) as usize);
-                    format!("${:04x}", address)
-                }
-
-                _ => panic!(
-                    "unexpected addressing mode {:?} has ops-len 2. code {:02x}",
-                    opscode_data.mode, opscode_data.code
-                ),
-            }
-        }
-        3 => {
-            let address_lo = cpu.mem_read(program_counter + 1);
-            let address_hi = cpu.mem_read(program_counter + 2);
-            hex_dump.push(address_lo);
-            hex_dump.push(address_hi);
-
-            let address = cpu.mem_read_u16(program_counter + 1);
-
-            match opscode_data.mode {
-                AddressingMode::NotSupported => {
-                    if opscode_data.code == 0x6c {
-                        //jmp indirect
-                        let jmp_addr = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.mem_read(address);
-                            let hi = cpu.mem_read(address & 0xFF00);
-                            (hi as u16) << 8 | (lo as u16)
-                        } else {
-                            cpu.mem_read_u16(address)
-                        };
-
-                        // let jmp_addr = cpu.mem_read_u16(address);
-                        format!("(${:04x}) = {:04x}", address, jmp_addr)
-                    } else {
-                        format!("${:04x}", address)
-                    }
-                }
-                AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, stored_value),
-                AddressingMode::Absolute_X => format!(
-                    "${:04x},X @ {:04x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                AddressingMode::Absolute_Y => format!(
-                    "${:04x},Y @ {:04x} = {:02x}",
-                    address, mem_addr, stored_value
-                ),
-                _ => panic!(
-                    "unexpected addressing mode {:?} has ops-len 3. code {:02x}",
-                    opscode_data.mode, opscode_data.code
-                ),
-            }
-        }
-        _ => String::from(""),
-    };
+    }
+}
 
-    let hex_str = hex_dump
-        .iter()
-        .map(|z| format!("{:02x}", z))
-        .collect::<Vec<String>>()
-        .join(" ");
-    let asm_str = format!(
-        "{:04x}  {:8} {: >4} {}",
-        program_counter, hex_str, opscode_data.name, tmp
-    )
-    .trim()
-    .to_string();
-    format!("{:47} {} {}", asm_str, registers, ppuinfo).to_ascii_uppercase()
+//files named on the command line must load, anything wrong with them ends the program
+fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>, file: &str) -> T {
+    result.unwrap_or_else(|e| {
+        println!("Could not load {}: {}", file, e);
+        std::process::exit(1)
+    })
 }
+
 fn main() {
     env::set_var("RUST_BACKTRACE", "full");
     let mut key_map = HashMap::new();
-    key_map.insert(Keycode::S, &joypads::Button::Down);
-    key_map.insert(Keycode::W, &joypads::Button::Up);
-    key_map.insert(Keycode::D, &joypads::Button::Right);
-    key_map.insert(Keycode::A, &joypads::Button::Left);
-    key_map.insert(Keycode::U, &joypads::Button::Select);
-    key_map.insert(Keycode::I, &joypads::Button::Start);
-    key_map.insert(Keycode::J, &joypads::Button::A);
-    key_map.insert(Keycode::K, &joypads::Button::B);
+    key_map.insert(Keycode::S, Button::Down);
+    key_map.insert(Keycode::W, Button::Up);
+    key_map.insert(Keycode::D, Button::Right);
+    key_map.insert(Keycode::A, Button::Left);
+    key_map.insert(Keycode::U, Button::Select);
+    key_map.insert(Keycode::I, Button::Start);
+    key_map.insert(Keycode::J, Button::A);
+    key_map.insert(Keycode::K, Button::B);
     //init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsytem = sdl_context.video().unwrap();
@@ -246,7 +73,8 @@ fn main() {
         .position_centered()
         .build()
         .unwrap();
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    //audio drives the pace, so no vsync
+    let mut canvas = window.into_canvas().build().unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
     canvas.set_scale(3.0, 3.0).unwrap();
     //texture for render
@@ -254,14 +82,116 @@ fn main() {
     let mut texture = creator
         .create_texture_target(PixelFormatEnum::RGB24, 256, 240)
         .unwrap();
-    //load program
-    let bytes: Vec<u8> = std::fs::read("../../pacman.nes").unwrap();
-    let rom = Rom::new(&bytes).unwrap();
-    let mut frame = Frame::new();
+    /*
+    nes <rom> [--state file] [--record file] [--play file]
+    --state starts from a save state, --record writes the session's inputs to a movie
+    on exit and --play runs a movie (ours or an FCEUX .fm2) before handing control back
+     */
+    let args: Vec<String> = env::args().collect();
+    let flag = |name: &str| {
+        let index = args.iter().position(|arg| arg == name)?;
+        args.get(index + 1).cloned()
+    };
+    let path = args
+        .get(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .cloned()
+        .unwrap_or(String::from("../../pacman.nes"));
+    let bytes: Vec<u8> = or_exit(std::fs::read(&path), &path);
+    let rom = or_exit(Rom::new(&bytes), &path);
+    let mut console = or_exit(Console::new(rom), &path);
+    let start_state = flag("--state");
+    if let Some(state_file) = &start_state {
+        let data = or_exit(std::fs::read(state_file), state_file);
+        or_exit(console.load_state(&data), state_file);
+    }
+    let mut recording = flag("--record").map(|movie_file| {
+        (
+            movie_file,
+            Movie::record(&mut console, start_state.is_some()),
+        )
+    });
+    let mut playing = flag("--play").map(|movie_file| {
+        let data = or_exit(std::fs::read(&movie_file), &movie_file);
+        let movie = if movie_file.ends_with(".fm2") {
+            Movie::from_fm2(&String::from_utf8_lossy(&data))
+        } else {
+            Movie::from_bytes(&data)
+        };
+        let movie = or_exit(movie, &movie_file);
+        or_exit(movie.start(&mut console), &movie_file);
+        movie
+    });
+    let mut movie_frame = 0;
+    //movies must not see or change the player's battery save
+    let mut save_file = None;
+    if recording.is_none() && playing.is_none() {
+        let mut battery = SaveFile::new(Path::new(&path));
+        if let Err(e) = battery.load(&mut console) {
+            println!("Could not load {}: {}", battery.path.display(), e);
+        }
+        save_file = Some(battery);
+    }
+    //F5 writes a save state next to the rom, F7 reads it back
+    let state_path = Path::new(&path).with_extension("state");
+    //holding backspace runs the game backwards
+    let mut rewind = Rewind::new(REWIND_INTERVAL, REWIND_GROUP_SIZE, REWIND_BUDGET);
+    let mut rewinding = false;
+    //audio, falls back to sleeping a frame when there is no output device
+    let (mut producer, consumer) = ring_buffer(AUDIO_TARGET_FILL * 4);
+    let desired = AudioSpecDesired {
+        freq: Some(44100),
+        channels: Some(1),
+        samples: Some(512),
+    };
+    let audio_device = sdl_context.audio().and_then(|audio| {
+        audio.open_playback(None, &desired, |spec| {
+            console.set_sample_rate(spec.freq as u32);
+            AudioOut {
+                samples: consumer,
+                last: 0.0,
+            }
+        })
+    });
+    if let Ok(device) = &audio_device {
+        device.resume();
+    }
     //game cycle
-    let bus = Bus::new(rom, move |ppu: &PPU, joypad1: &mut joypads::Joypad| {
-        render::render(ppu, &mut frame);
-        texture.update(None, &frame.data, 256 * 3).unwrap();
+    let mut frames: u32 = 0;
+    loop {
+        if audio_device.is_ok() {
+            while producer.len() > AUDIO_TARGET_FILL {
+                ::std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        if rewinding {
+            rewind.step_back(&mut console);
+        } else {
+            if let Some(movie) = &playing {
+                if movie.play_frame(&mut console, movie_frame) {
+                    movie_frame += 1;
+                } else {
+                    println!("Movie finished after {} frames", movie_frame);
+                    console.set_buttons(0);
+                    playing = None;
+                }
+            }
+            console.step_frame();
+            if let Some((_, movie)) = &mut recording {
+                movie.record_frame(&console);
+            }
+            rewind.push(&mut console);
+        }
+        frames += 1;
+        if frames % SAVE_FLUSH_FRAMES == 0 {
+            flush_battery(&mut save_file, &console);
+        }
+        for sample in console.take_audio_samples() {
+            producer.push(sample);
+        }
+        texture
+            .update(None, &console.framebuffer().data, 256 * 3)
+            .unwrap();
         canvas.copy(&texture, None, None).unwrap();
         canvas.present();
         for event in event_pump.poll_iter() {
@@ -270,45 +200,61 @@ fn main() {
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => std::process::exit(0),
+                } => {
+                    flush_battery(&mut save_file, &console);
+                    if let Some((movie_file, movie)) = &recording {
+                        if let Err(e) = std::fs::write(movie_file, movie.to_bytes()) {
+                            println!("Could not write {}: {}", movie_file, e);
+                        }
+                    }
+                    std::process::exit(0)
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    if let Err(e) = std::fs::write(&state_path, console.save_state()) {
+                        println!("Could not write {}: {}", state_path.display(), e);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } if recording.is_none() && playing.is_none() => match std::fs::read(&state_path) {
+                    Ok(data) => match console.load_state(&data) {
+                        Ok(()) => rewind.clear(),
+                        Err(e) => println!("Could not load {}: {}", state_path.display(), e),
+                    },
+                    Err(e) => println!("Could not read {}: {}", state_path.display(), e),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } if recording.is_none() && playing.is_none() => rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
                 Event::KeyDown { keycode, .. } => {
                     if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        joypad1.set_button(*key, true);
+                        console.set_button(key, true);
                     }
                 }
                 Event::KeyUp { keycode, .. } => {
                     if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        joypad1.set_button(*key, false);
+                        console.set_button(key, false);
                     }
                 }
                 _ => { /* do nothing */ }
             }
         }
-        ::std::thread::sleep(std::time::Duration::from_nanos(5000000));
-    });
-    let mut cpu = CPU::new(bus);
-    cpu.reset();
-    //cpu.program_counter = 0xC000; //for nesttest rom
-    cpu.bus.tick(7); //for nestest rom
-    cpu.run();
-    /*cpu.run_with_callback(move |cpu| {
-        println!("{}", trace(cpu));
-        ::std::thread::sleep(std::time::Duration::from_nanos(100000000));
-    });*/
-
-    /*
-    //run program
-    let mut screen_state = [0 as u8; 32 * 3 * 32];
-    let mut rng = rand::thread_rng();
-    cpu.run_with_callback(move |cpu| {
-        //println!("{}", trace(cpu));//for nesttest rom
-        input_handler(cpu, &mut event_pump);
-        cpu.mem_write(0xfe, rng.gen_range(1..16));
-        if read_screen_state(cpu, &mut screen_state) {
-            texture.update(None, &screen_state, 32 * 3).unwrap();
-            canvas.copy(&texture, None, None).unwrap();
-            canvas.present();
-            ::std::thread::sleep(std::time::Duration::from_nanos(10000000));
+        //no audio is queued while rewinding, so pace those frames by sleeping too
+        if audio_device.is_err() || rewinding {
+            ::std::thread::sleep(Duration::from_nanos(16_639_267));
         }
+    }
+    /*console.cpu.run_with_callback(move |cpu| {
+        println!("{}", nes::trace::trace(cpu));
+        ::std::thread::sleep(std::time::Duration::from_nanos(100000000));
     });*/
 }
@@ -0,0 +1,83 @@
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 7. Writes to $8000-$FFFF:
+7  bit  0
+---- ----
+xxxM xPPP
+   |  |||
+   |  +++- 32K prg bank at $8000
+   +------ one-screen nametable (0: lower, 1: upper)
+Boards carry 8K of chr-ram.
+ */
+pub struct AxROM {
+    pub prg_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub bank_select: u8,
+}
+impl AxROM {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        AxROM {
+            prg_rom: rom.prg_rom,
+            prg_ram: prg_ram_memory(&rom.header),
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            bank_select: 0,
+        }
+    }
+}
+impl Mapper for AxROM {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => read_prg_ram(&self.prg_ram, (addr - 0x6000) as usize),
+            0x8000..=0xffff => {
+                let bank = (self.bank_select & 0b111) as usize;
+                let addr = bank * 0x8000 + (addr - 0x8000) as usize;
+                self.prg_rom[addr % self.prg_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7fff = addr {
+            write_prg_ram(&mut self.prg_ram, (addr - 0x6000) as usize, data);
+        } else if addr >= 0x8000 {
+            self.bank_select = data;
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[addr as usize]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        self.chr[addr as usize] = data;
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        if self.bank_select & 0b1_0000 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+}
+impl Snapshot for AxROM {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.u8(&mut self.bank_select);
+    }
+}
@@ -0,0 +1,59 @@
+use crate::mapper::{prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 3. Prg-rom is laid out like NROM, any write to $8000-$FFFF selects
+the 8K chr bank.
+ */
+pub struct CNROM {
+    pub prg_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mirroring: Mirroring,
+    pub chr_bank: u8,
+}
+impl CNROM {
+    pub fn new(rom: Rom) -> Self {
+        CNROM {
+            prg_rom: rom.prg_rom,
+            prg_ram: prg_ram_memory(&rom.header),
+            chr_rom: rom.chr_rom,
+            mirroring: rom.header.mirroring,
+            chr_bank: 0,
+        }
+    }
+}
+impl Mapper for CNROM {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => read_prg_ram(&self.prg_ram, (addr - 0x6000) as usize),
+            0x8000..=0xffff => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7fff = addr {
+            write_prg_ram(&mut self.prg_ram, (addr - 0x6000) as usize, data);
+        } else if addr >= 0x8000 {
+            self.chr_bank = data;
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        let addr = self.chr_bank as usize * 0x2000 + addr as usize;
+        self.chr_rom[addr % self.chr_rom.len()]
+    }
+    fn write_chr(&mut self, _addr: u16, _data: u8) {}
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+impl Snapshot for CNROM {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        s.u8(&mut self.chr_bank);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::apu::PULSE_LEVEL;
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 69, Sunsoft FME-7 and the 5B (an FME-7 with a YM2149 style sound chip).
+$8000 command   ---- CCCC  register for the parameter port
+$A000 parameter value for the command
+  $0-$7  chr banks, 1K each
+  $8     ERxx xxxx  prg at $6000, E: ram enable, R: ram instead of rom, low 6 bits rom bank
+  $9-$B  8K prg banks at $8000, $A000, $C000
+  $C     mirroring  0: vertical, 1: horizontal, 2: one screen lower, 3: one screen upper
+  $D     irq control  C--- ---I  counter enable, irq enable. writes acknowledge the irq
+  $E/$F  irq counter low/high
+$C000 audio register select
+$E000 audio register data
+$E000 is fixed to the last 8K bank. The irq counter counts down every cpu cycle and
+raises the irq when it wraps from $0000 to $FFFF.
+
+Audio registers, three square channels A B C, one noise source and one envelope
+  $0-$5  tone periods, 12 bits as low/high pairs
+  $6     noise period (5 bits)
+  $7     --CB Acba  noise disable for C B A, tone disable for c b a
+  $8-$A  ---E VVVV  envelope instead of volume, volume
+  $B/$C  envelope period
+  $D     ---- CAaH  envelope shape: continue, attack, alternate, hold
+Everything counts in units of 16 cpu cycles. A square toggles every period units, the
+noise lfsr steps every period units and the envelope moves one of 16 levels every
+envelope period units.
+ */
+const AUDIO_DIVIDER: u8 = 16;
+//volume levels are 3dB apart, level 15 on one channel is a little over an apu pulse
+const SUNSOFT5B_LEVEL: f32 = PULSE_LEVEL * 1.2;
+pub struct FME7 {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub command: u8,
+    pub chr_banks: [u8; 8],
+    pub prg_banks: [u8; 4],
+    pub mirroring: Mirroring,
+    pub irq_enabled: bool,
+    pub irq_counter_enabled: bool,
+    pub irq_counter: u16,
+    pub irq_flag: bool,
+    pub audio_register: u8,
+    pub audio_registers: [u8; 16],
+    pub audio_divider: u8,
+    pub tone_timers: [u16; 3],
+    pub tone_outputs: [bool; 3],
+    pub noise_timer: u8,
+    pub noise_lfsr: u32,
+    pub envelope_timer: u16,
+    pub envelope_step: u8,
+    pub envelope_attack: bool,
+    pub envelope_holding: bool,
+    pub levels: [f32; 16],
+}
+impl FME7 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        let mut levels = [0.0; 16];
+        for level in 1..16 {
+            levels[level] = 10f32.powf(-3.0 * (15 - level) as f32 / 20.0);
+        }
+        FME7 {
+            prg_rom: rom.prg_rom,
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            prg_ram: prg_ram_memory(&rom.header),
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            mirroring: Mirroring::Vertical,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_flag: false,
+            audio_register: 0,
+            audio_registers: [0; 16],
+            audio_divider: 0,
+            tone_timers: [0; 3],
+            tone_outputs: [false; 3],
+            noise_timer: 0,
+            noise_lfsr: 1,
+            envelope_timer: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: true,
+            levels: levels,
+        }
+    }
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8..=0xb => self.prg_banks[(self.command - 8) as usize] = data,
+            0xc => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            0xd => {
+                self.irq_enabled = data & 0b0000_0001 != 0;
+                self.irq_counter_enabled = data & 0b1000_0000 != 0;
+                self.irq_flag = false;
+            }
+            0xe => self.irq_counter = (self.irq_counter & 0xff00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00ff) | (data as u16) << 8,
+        }
+    }
+    fn write_audio(&mut self, data: u8) {
+        self.audio_registers[self.audio_register as usize] = data;
+        if self.audio_register == 0xd {
+            self.envelope_step = 0;
+            self.envelope_attack = data & 0b0100 != 0;
+            self.envelope_holding = false;
+            self.envelope_timer = 0;
+        }
+    }
+    fn tone_period(&self, channel: usize) -> u16 {
+        let lo = self.audio_registers[channel * 2] as u16;
+        let hi = self.audio_registers[channel * 2 + 1] as u16 & 0x0f;
+        (hi << 8 | lo).max(1)
+    }
+    fn clock_audio(&mut self) {
+        for channel in 0..3 {
+            self.tone_timers[channel] += 1;
+            if self.tone_timers[channel] >= self.tone_period(channel) {
+                self.tone_timers[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+        self.noise_timer += 1;
+        if self.noise_timer >= (self.audio_registers[6] & 0x1f).max(1) {
+            self.noise_timer = 0;
+            //17 bit lfsr, taps at bits 0 and 3
+            let feedback = (self.noise_lfsr ^ (self.noise_lfsr >> 3)) & 1;
+            self.noise_lfsr = (self.noise_lfsr >> 1) | feedback << 16;
+        }
+        let envelope_period =
+            (self.audio_registers[0xb] as u16 | (self.audio_registers[0xc] as u16) << 8).max(1);
+        self.envelope_timer += 1;
+        if self.envelope_timer >= envelope_period {
+            self.envelope_timer = 0;
+            self.clock_envelope();
+        }
+    }
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        self.envelope_step += 1;
+        if self.envelope_step < 16 {
+            return;
+        }
+        let shape = self.audio_registers[0xd];
+        let (continuing, alternate, hold) = (
+            shape & 0b1000 != 0,
+            shape & 0b0010 != 0,
+            shape & 0b0001 != 0,
+        );
+        if !continuing {
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+            self.envelope_step = 15;
+        } else if hold {
+            self.envelope_holding = true;
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 15;
+        } else {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            15 - self.envelope_step
+        }
+    }
+}
+impl Mapper for FME7 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x6000..=0x7fff => {
+                let control = self.prg_banks[0];
+                if control & 0b0100_0000 != 0 {
+                    return if control & 0b1000_0000 != 0 {
+                        read_prg_ram(&self.prg_ram, (addr - 0x6000) as usize)
+                    } else {
+                        0
+                    };
+                }
+                (control & 0b0011_1111) as usize
+            }
+            0x8000..=0x9fff => self.prg_banks[1] as usize,
+            0xa000..=0xbfff => self.prg_banks[2] as usize,
+            0xc000..=0xdfff => self.prg_banks[3] as usize,
+            0xe000..=0xffff => self.prg_rom.len() / 0x2000 - 1,
+            _ => return 0,
+        };
+        self.prg_rom[(bank * 0x2000 + (addr & 0x1fff) as usize) % self.prg_rom.len()]
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff => {
+                if self.prg_banks[0] & 0b1100_0000 == 0b1100_0000 {
+                    write_prg_ram(&mut self.prg_ram, (addr - 0x6000) as usize, data);
+                }
+            }
+            0x8000..=0x9fff => self.command = data & 0x0f,
+            0xa000..=0xbfff => self.write_parameter(data),
+            0xc000..=0xdfff => self.audio_register = data & 0x0f,
+            0xe000..=0xffff => self.write_audio(data),
+            _ => {}
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+        self.chr[(bank * 0x400 + (addr & 0x3ff) as usize) % self.chr.len()]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+            let index = (bank * 0x400 + (addr & 0x3ff) as usize) % self.chr.len();
+            self.chr[index] = data;
+        }
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+    fn clock(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xffff && self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+        self.audio_divider += 1;
+        if self.audio_divider == AUDIO_DIVIDER {
+            self.audio_divider = 0;
+            self.clock_audio();
+        }
+    }
+    fn audio_output(&self) -> f32 {
+        let mixer = self.audio_registers[7];
+        let noise = self.noise_lfsr & 1 != 0;
+        let mut total = 0.0;
+        for channel in 0..3 {
+            let tone = self.tone_outputs[channel] || mixer & (1 << channel) != 0;
+            let noise = noise || mixer & (0b1000 << channel) != 0;
+            if tone && noise {
+                let volume = self.audio_registers[8 + channel];
+                let level = if volume & 0b1_0000 != 0 {
+                    self.envelope_level()
+                } else {
+                    volume & 0x0f
+                };
+                total += self.levels[level as usize];
+            }
+        }
+        total * SUNSOFT5B_LEVEL
+    }
+    fn irq_pending(&self) -> bool {
+        self.irq_flag
+    }
+}
+impl Snapshot for FME7 {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.u8(&mut self.command);
+        s.bytes(&mut self.chr_banks);
+        s.bytes(&mut self.prg_banks);
+        s.mirroring(&mut self.mirroring);
+        s.bool(&mut self.irq_enabled);
+        s.bool(&mut self.irq_counter_enabled);
+        s.u16(&mut self.irq_counter);
+        s.bool(&mut self.irq_flag);
+        s.u8_below(&mut self.audio_register, 16);
+        s.bytes(&mut self.audio_registers);
+        s.u8_below(&mut self.audio_divider, AUDIO_DIVIDER);
+        //tone periods are 12 bits and the noise period 5
+        for value in self.tone_timers.iter_mut() {
+            s.u16_range(value, 0..=0xfff);
+        }
+        for output in self.tone_outputs.iter_mut() {
+            s.bool(output);
+        }
+        s.u8_below(&mut self.noise_timer, 32);
+        s.u32(&mut self.noise_lfsr);
+        s.u16_range(&mut self.envelope_timer, 0..=0xfffe);
+        s.u8_below(&mut self.envelope_step, 16);
+        s.bool(&mut self.envelope_attack);
+        s.bool(&mut self.envelope_holding);
+    }
+}
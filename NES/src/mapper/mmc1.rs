@@ -0,0 +1,228 @@
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 1. The cpu loads registers one bit at a time through $8000-$FFFF:
+bit 7 set resets the shift register, otherwise bit 0 is shifted in and the
+fifth write copies the value into the register picked by address bits 13-14.
+
+Control ($8000-$9FFF)
+4bit0
+-----
+CPPMM
+|||||
+|||++- Mirroring (0: one-screen lower, 1: one-screen upper, 2: vertical, 3: horizontal)
+|++--- PRG mode (0, 1: 32K at $8000, 2: first bank fixed at $8000, 3: last bank fixed at $C000)
++----- CHR mode (0: one 8K bank, 1: two 4K banks)
+
+CHR bank 0 ($A000-$BFFF), CHR bank 1 ($C000-$DFFF)
+4bit0
+-----
+CCCCC  4K bank at $0000/$1000 (low bit ignored in 8K mode)
+On boards with chr-ram (SNROM, SOROM, SUROM, SXROM) the upper bits go to the
+prg side instead: bit 4 picks the 256K prg half, and the 8K prg-ram bank is bit 3
+with 16K of ram (SOROM) or bits 2-3 with 32K (SXROM). SNROM, with no outer bank and
+8K of ram, disables the ram while bit 4 is set.
+
+PRG bank ($E000-$FFFF)
+4bit0
+-----
+RPPPP
+|++++- 16K bank (low bit ignored in 32K mode)
++----- PRG-RAM disable
+NES 2.0 submapper 5 (SEROM, SHROM, SH1ROM) has 32K of prg-rom that is not banked.
+ */
+pub struct MMC1 {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub shift: u8,
+    pub shift_count: u8,
+    pub control: u8,
+    pub chr_bank0: u8,
+    pub chr_bank1: u8,
+    pub prg_bank: u8,
+    pub fixed_prg: bool,
+}
+impl MMC1 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        MMC1 {
+            prg_rom: rom.prg_rom,
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            prg_ram: prg_ram_memory(&rom.header),
+            shift: 0,
+            shift_count: 0,
+            control: 0x0c,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            fixed_prg: rom.header.submapper == 5,
+        }
+    }
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9fff => self.control = data,
+            0xa000..=0xbfff => self.chr_bank0 = data,
+            0xc000..=0xdfff => self.chr_bank1 = data,
+            _ => self.prg_bank = data,
+        }
+    }
+    //256K outer bank, only SUROM/SXROM have more than 256K of prg-rom
+    fn prg_outer(&self) -> usize {
+        if self.chr_is_ram && self.prg_rom.len() > 0x40000 {
+            (self.chr_bank0 as usize >> 4 & 1) * 0x40000
+        } else {
+            0
+        }
+    }
+    fn prg_address(&self, addr: u16) -> usize {
+        if self.fixed_prg {
+            return (addr & 0x7fff) as usize % self.prg_rom.len();
+        }
+        let bank = (self.prg_bank & 0b1111) as usize;
+        let inner = addr as usize & 0x3fff;
+        //with an outer bank the fixed banks are the first and last of each 256K half
+        let last = (self.prg_rom.len().min(0x40000) / 0x4000).saturating_sub(1);
+        let bank = match (self.control >> 2 & 0b11, addr) {
+            (0 | 1, 0x8000..=0xbfff) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            (2, 0x8000..=0xbfff) => 0,
+            (2, _) => bank,
+            (_, 0x8000..=0xbfff) => bank,
+            (_, _) => last,
+        };
+        (self.prg_outer() + bank * 0x4000 + inner) % self.prg_rom.len()
+    }
+    //SNROM's gate is wired to the chr bank bit, which we take from the $0000 bank
+    fn prg_ram_enabled(&self) -> bool {
+        let snrom =
+            self.chr_is_ram && self.prg_rom.len() <= 0x40000 && self.prg_ram.len() <= 0x2000;
+        let snrom_disabled = snrom && self.chr_bank0 & 0b1_0000 != 0;
+        self.prg_bank & 0b1_0000 == 0 && !snrom_disabled
+    }
+    fn prg_ram_address(&self, addr: u16) -> usize {
+        let bank = match self.prg_ram.len() {
+            _ if !self.chr_is_ram => 0,
+            0x8000 => (self.chr_bank0 as usize >> 2) & 0b11,
+            0x4000 => (self.chr_bank0 as usize >> 3) & 0b1,
+            _ => 0,
+        };
+        bank * 0x2000 + (addr - 0x6000) as usize
+    }
+    fn chr_address(&self, addr: u16) -> usize {
+        let addr = if self.control & 0b1_0000 == 0 {
+            (self.chr_bank0 & 0b1_1110) as usize * 0x1000 + addr as usize
+        } else if addr < 0x1000 {
+            self.chr_bank0 as usize * 0x1000 + addr as usize
+        } else {
+            self.chr_bank1 as usize * 0x1000 + (addr & 0x0fff) as usize
+        };
+        addr % self.chr.len()
+    }
+}
+impl Mapper for MMC1 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                read_prg_ram(&self.prg_ram, self.prg_ram_address(addr))
+            }
+            0x8000..=0xffff => self.prg_rom[self.prg_address(addr)],
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled() => {
+                let offset = self.prg_ram_address(addr);
+                write_prg_ram(&mut self.prg_ram, offset, data);
+            }
+            0x8000..=0xffff => {
+                if data & 0b1000_0000 != 0 {
+                    self.shift = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0c;
+                    return;
+                }
+                self.shift |= (data & 1) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    self.write_register(addr, self.shift);
+                    self.shift = 0;
+                    self.shift_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.chr_address(addr)]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_address(addr);
+        self.chr[index] = data;
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+}
+impl Snapshot for MMC1 {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.u8(&mut self.shift);
+        s.u8_below(&mut self.shift_count, 5);
+        s.u8(&mut self.control);
+        s.u8(&mut self.chr_bank0);
+        s.u8(&mut self.chr_bank1);
+        s.u8(&mut self.prg_bank);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    //16K of prg, 8K of chr-ram and 8K of prg-ram, laid out like SNROM
+    fn snrom() -> MMC1 {
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0x10];
+        raw.resize(16 + 0x4000, 0);
+        MMC1::new(Rom::new(&raw).unwrap())
+    }
+    //five writes, lowest bit first
+    fn write_register(mmc1: &mut MMC1, addr: u16, data: u8) {
+        for i in 0..5 {
+            mmc1.write_prg(addr, data >> i & 1);
+        }
+    }
+    #[test]
+    fn snrom_chr_bank_bit_4_disables_prg_ram() {
+        let mut mmc1 = snrom();
+        mmc1.write_prg(0x6000, 0x55);
+        assert_eq!(mmc1.read_prg(0x6000), 0x55);
+        write_register(&mut mmc1, 0xa000, 0b1_0000);
+        assert_eq!(mmc1.read_prg(0x6000), 0);
+        mmc1.write_prg(0x6000, 0xaa);
+        write_register(&mut mmc1, 0xa000, 0);
+        assert_eq!(mmc1.read_prg(0x6000), 0x55);
+        //the prg bank register's disable bit still applies
+        write_register(&mut mmc1, 0xe000, 0b1_0000);
+        assert_eq!(mmc1.read_prg(0x6000), 0);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 4. Registers are paired even/odd across $8000-$FFFF:
+$8000 bank select   CPxx xRRR  chr A12 inversion, prg mode, bank register for $8001
+$8001 bank data     value for R0-R7
+$A000 mirroring     xxxx xxxM  0: vertical, 1: horizontal
+$A001 prg-ram       EWxx xxxx  enable, write protect
+$C000 irq latch     counter reload value
+$C001 irq reload    counter is reloaded on the next A12 edge
+$E000 irq disable   also acknowledges a pending irq
+$E001 irq enable
+
+Bank layout, the inversion bit swaps the chr halves and the prg mode swaps $8000 and $C000
+  chr  $0000 R0 (2K)  $0800 R1 (2K)  $1000 R2  $1400 R3  $1800 R4  $1C00 R5
+  prg  $8000 R6  $A000 R7  $C000 second last  $E000 last
+
+Four screen boards carry 2K of ram for nametables 2 and 3, the console's vram holds 0 and 1.
+
+The irq counter is clocked by rising edges of ppu A12. Edges only count after A12 has
+been low for a few cpu cycles, which filters out the toggling within the sprite fetches.
+ */
+const A12_FILTER_DOTS: u64 = 10;
+pub struct MMC3 {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub four_screen: bool,
+    pub four_screen_ram: Vec<u8>,
+    pub bank_select: u8,
+    pub registers: [u8; 8],
+    pub mirroring: Mirroring,
+    pub prg_ram_enabled: bool,
+    pub prg_ram_protected: bool,
+    pub irq_latch: u8,
+    pub irq_counter: u8,
+    pub irq_reload: bool,
+    pub irq_enabled: bool,
+    pub irq_flag: bool,
+    pub last_a12_high: u64,
+}
+impl MMC3 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        let four_screen = rom.header.mirroring == Mirroring::Fourscreen;
+        MMC3 {
+            prg_rom: rom.prg_rom,
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            prg_ram: prg_ram_memory(&rom.header),
+            four_screen: four_screen,
+            four_screen_ram: vec![0; if four_screen { 0x800 } else { 0 }],
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.header.mirroring,
+            prg_ram_enabled: true,
+            prg_ram_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_flag: false,
+            last_a12_high: 0,
+        }
+    }
+    fn prg_address(&self, addr: u16) -> usize {
+        let last = self.prg_rom.len() / 0x2000 - 1;
+        let swap = self.bank_select & 0b0100_0000 != 0;
+        let bank = match (addr, swap) {
+            (0x8000..=0x9fff, false) | (0xc000..=0xdfff, true) => self.registers[6] as usize,
+            (0x8000..=0x9fff, true) | (0xc000..=0xdfff, false) => last - 1,
+            (0xa000..=0xbfff, _) => self.registers[7] as usize,
+            _ => last,
+        };
+        (bank * 0x2000 + (addr & 0x1fff) as usize) % self.prg_rom.len()
+    }
+    fn chr_address(&self, addr: u16) -> usize {
+        let addr = if self.bank_select & 0b1000_0000 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        let bank = match addr {
+            0x0000..=0x07ff => (self.registers[0] & 0xfe) as usize + (addr >> 10) as usize,
+            0x0800..=0x0fff => (self.registers[1] & 0xfe) as usize + (addr >> 10 & 1) as usize,
+            _ => self.registers[2 + (addr as usize - 0x1000) / 0x400] as usize,
+        };
+        (bank * 0x400 + (addr & 0x3ff) as usize) % self.chr.len()
+    }
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_flag = true;
+        }
+    }
+}
+impl Mapper for MMC3 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.prg_ram_enabled => {
+                read_prg_ram(&self.prg_ram, (addr - 0x6000) as usize)
+            }
+            0x8000..=0xffff => self.prg_rom[self.prg_address(addr)],
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7fff => {
+                if self.prg_ram_enabled && !self.prg_ram_protected {
+                    write_prg_ram(&mut self.prg_ram, (addr - 0x6000) as usize, data);
+                }
+            }
+            0x8000..=0x9fff if even => self.bank_select = data,
+            0x8000..=0x9fff => self.registers[(self.bank_select & 0b111) as usize] = data,
+            0xa000..=0xbfff if even => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            0xa000..=0xbfff => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.prg_ram_protected = data & 0b0100_0000 != 0;
+            }
+            0xc000..=0xdfff if even => self.irq_latch = data,
+            0xc000..=0xdfff => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xe000..=0xffff if even => {
+                self.irq_enabled = false;
+                self.irq_flag = false;
+            }
+            0xe000..=0xffff => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.chr_address(addr)]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_address(addr);
+        self.chr[index] = data;
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::Fourscreen
+        } else {
+            self.mirroring
+        }
+    }
+    fn read_nametable(&mut self, addr: u16) -> Option<u8> {
+        if self.four_screen && addr & 0x800 != 0 {
+            Some(self.four_screen_ram[(addr & 0x7ff) as usize])
+        } else {
+            None
+        }
+    }
+    fn write_nametable(&mut self, addr: u16, data: u8) -> bool {
+        let mapped = self.four_screen && addr & 0x800 != 0;
+        if mapped {
+            self.four_screen_ram[(addr & 0x7ff) as usize] = data;
+        }
+        mapped
+    }
+    fn irq_pending(&self) -> bool {
+        self.irq_flag
+    }
+    fn ppu_address(&mut self, addr: u16, dot: u64) {
+        if addr & 0x1000 == 0 {
+            return;
+        }
+        if dot.saturating_sub(self.last_a12_high) >= A12_FILTER_DOTS {
+            self.clock_irq_counter();
+        }
+        self.last_a12_high = dot;
+    }
+}
+impl Snapshot for MMC3 {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        s.bytes(&mut self.four_screen_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.u8(&mut self.bank_select);
+        s.bytes(&mut self.registers);
+        s.mirroring(&mut self.mirroring);
+        s.bool(&mut self.prg_ram_enabled);
+        s.bool(&mut self.prg_ram_protected);
+        s.u8(&mut self.irq_latch);
+        s.u8(&mut self.irq_counter);
+        s.bool(&mut self.irq_reload);
+        s.bool(&mut self.irq_enabled);
+        s.bool(&mut self.irq_flag);
+        s.u64(&mut self.last_a12_high);
+    }
+}
@@ -0,0 +1,477 @@
+use crate::apu::pulse::Pulse;
+use crate::apu::{pulse_mix, tnd_mix};
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 5. Registers at $5100-$5206, 1K of ExRAM at $5C00-$5FFF.
+$5100 prg mode       0: 32K, 1: 16K+16K, 2: 16K+8K+8K, 3: four 8K banks
+$5101 chr mode       0: 8K, 1: 4K, 2: 2K, 3: 1K pages
+$5102/$5103          prg-ram writes need $02 and $01 written here
+$5104 exram mode     0: nametable, 1: extended attributes, 2: cpu ram, 3: cpu rom
+$5105 nametables     DDCC BBAA  per nametable 0: vram page 0, 1: vram page 1, 2: exram, 3: fill
+$5106/$5107          fill mode tile and palette
+$5113                prg-ram bank at $6000
+$5114-$5117          prg banks, bit 7 picks rom over ram ($5117 is always rom)
+$5120-$5127          chr set A, sprites (and everything with 8x8 sprites)
+$5128-$512B          chr set B, background when sprites are 8x16
+$5130                upper chr bank bits
+$5200 split          ES-T TTTT  enable, right side, tile threshold
+$5201/$5202          split y scroll and 4K chr page
+$5203 irq compare    scanline the irq fires on
+$5204 irq status     read: pending, in frame. write: enable
+$5205/$5206          8x8 bit multiplier, reads return the 16 bit product
+
+Audio, two copies of the apu pulse channel without the sweep unit and a raw pcm level
+$5000-$5003          pulse 1, laid out as $4000-$4003 ($5001 does nothing)
+$5004-$5007          pulse 2
+$5011                pcm level (writes of $00 are ignored)
+$5015                length counter enables and status, as $4015
+The envelopes and length counters run off a fixed 240Hz timer instead of a frame counter.
+
+The chip has no scanline input. It watches the ppu bus instead: three reads of the
+same nametable address in a row are the dummy fetches at the end of a line, and no
+ppu reads for a few cpu cycles means the frame is over. Counting nametable reads
+since the last scanline gives the tile column for split screen and exram attributes.
+ */
+pub struct MMC5 {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub exram: [u8; 0x400],
+    pub prg_mode: u8,
+    pub chr_mode: u8,
+    pub prg_ram_protect: [u8; 2],
+    pub exram_mode: u8,
+    pub nametable_mapping: u8,
+    pub fill_tile: u8,
+    pub fill_attribute: u8,
+    pub prg_banks: [u8; 5],
+    pub chr_banks: [u16; 12],
+    pub chr_upper: u8,
+    pub last_chr_set_b: bool,
+    pub split_control: u8,
+    pub split_scroll: u8,
+    pub split_bank: u8,
+    pub irq_compare: u8,
+    pub irq_enabled: bool,
+    pub irq_pending: bool,
+    pub multiplicand: u8,
+    pub multiplier: u8,
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub pcm: u8,
+    pub audio_cycles: u16,
+    //what the chip has worked out from watching the buses
+    pub tall_sprites: bool,
+    pub rendering: bool,
+    pub in_frame: bool,
+    pub scanline: u16,
+    pub idle_cycles: u8,
+    pub last_nametable_addr: u16,
+    pub nametable_matches: u8,
+    pub tile_count: u16,
+    pub tile_exram: u8,
+    pub tile_split: bool,
+    pub split_y: u16,
+}
+impl MMC5 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        MMC5 {
+            prg_rom: rom.prg_rom,
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            prg_ram: prg_ram_memory(&rom.header),
+            exram: [0; 0x400],
+            prg_mode: 3,
+            chr_mode: 3,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0xff, 0xff, 0xff, 0xff],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            last_chr_set_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            pulse1: Pulse::new(2),
+            pulse2: Pulse::new(2),
+            pcm: 0,
+            audio_cycles: 0,
+            tall_sprites: false,
+            rendering: false,
+            in_frame: false,
+            scanline: 0,
+            idle_cycles: 0,
+            last_nametable_addr: 0,
+            nametable_matches: 0,
+            tile_count: 0,
+            tile_exram: 0,
+            tile_split: false,
+            split_y: 0,
+        }
+    }
+    //8K bank register behind a cpu address, and whether it points at rom
+    fn prg_bank(&self, addr: u16) -> (bool, usize) {
+        if addr < 0x8000 {
+            return (false, (self.prg_banks[0] & 0b111) as usize);
+        }
+        let slot = (addr - 0x8000) as usize / 0x2000;
+        let (register, mask, offset) = match (self.prg_mode & 0b11, slot) {
+            (0, _) => (4, !0b11, slot),
+            (1, 0 | 1) | (2, 0 | 1) => (2, !0b1, slot & 1),
+            (1, _) => (4, !0b1, slot - 2),
+            (_, _) => (slot + 1, !0, 0),
+        };
+        let value = self.prg_banks[register] & mask;
+        if register == 4 || value & 0b1000_0000 != 0 {
+            (true, (value & 0x7f) as usize + offset)
+        } else {
+            (false, (value & 0b111) as usize + offset)
+        }
+    }
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+    //1K chr bank for a pattern address through register set A or B
+    fn chr_bank(&self, addr: u16, set_b: bool) -> usize {
+        let slot = addr as usize / 0x400;
+        if set_b {
+            let r = &self.chr_banks[8..];
+            let slot = slot & 0b11;
+            match self.chr_mode & 0b11 {
+                0 => r[3] as usize * 8 + slot,
+                1 => r[3] as usize * 4 + slot,
+                2 => r[slot | 1] as usize * 2 + (slot & 1),
+                _ => r[slot] as usize,
+            }
+        } else {
+            let r = &self.chr_banks;
+            match self.chr_mode & 0b11 {
+                0 => r[7] as usize * 8 + slot,
+                1 => r[slot | 3] as usize * 4 + (slot & 3),
+                2 => r[slot | 1] as usize * 2 + (slot & 1),
+                _ => r[slot] as usize,
+            }
+        }
+    }
+    fn read_chr_bank(&self, addr: u16, set_b: bool) -> u8 {
+        let index = self.chr_bank(addr, set_b) * 0x400 + (addr & 0x3ff) as usize;
+        self.chr[index % self.chr.len()]
+    }
+    fn fetching_background(&self) -> bool {
+        self.in_frame && self.rendering
+    }
+    fn detect_scanline(&mut self) {
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline = 0;
+            self.irq_pending = false;
+        } else {
+            self.scanline += 1;
+            if self.scanline == self.irq_compare as u16 {
+                self.irq_pending = true;
+            }
+        }
+        //the read that completes the match is the first fetch of the visible line, tile 2
+        self.tile_count = 2;
+    }
+    fn in_split(&self, column: u16) -> bool {
+        if self.split_control & 0b1000_0000 == 0 || self.exram_mode > 1 {
+            return false;
+        }
+        let threshold = (self.split_control & 0b1_1111) as u16;
+        if self.split_control & 0b0100_0000 == 0 {
+            column < threshold
+        } else {
+            column >= threshold
+        }
+    }
+}
+impl Mapper for MMC5 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x5015 => self.pulse1.length.active() as u8 | (self.pulse2.length.active() as u8) << 1,
+            0x5204 => {
+                let status = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending = false;
+                status
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5c00..=0x5fff if self.exram_mode >= 2 => self.exram[(addr - 0x5c00) as usize],
+            0x6000..=0xffff => {
+                let (rom, bank) = self.prg_bank(addr);
+                let offset = (addr & 0x1fff) as usize;
+                if rom {
+                    self.prg_rom[(bank * 0x2000 + offset) % self.prg_rom.len()]
+                } else {
+                    read_prg_ram(&self.prg_ram, bank * 0x2000 + offset)
+                }
+            }
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5000 => self.pulse1.write_control(data),
+            0x5002 => self.pulse1.write_timer_lo(data),
+            0x5003 => self.pulse1.write_timer_hi(data),
+            0x5004 => self.pulse2.write_control(data),
+            0x5006 => self.pulse2.write_timer_lo(data),
+            0x5007 => self.pulse2.write_timer_hi(data),
+            0x5011 if data != 0 => self.pcm = data,
+            0x5015 => {
+                self.pulse1.length.set_enabled(data & 0b01 != 0);
+                self.pulse2.length.set_enabled(data & 0b10 != 0);
+            }
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => self.chr_mode = data & 0b11,
+            0x5102 => self.prg_ram_protect[0] = data & 0b11,
+            0x5103 => self.prg_ram_protect[1] = data & 0b11,
+            0x5104 => self.exram_mode = data & 0b11,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0b11,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+            0x5120..=0x512b => {
+                let index = (addr - 0x5120) as usize;
+                self.chr_banks[index] = data as u16 | (self.chr_upper as u16 & 0b11) << 8;
+                self.last_chr_set_b = index >= 8;
+            }
+            0x5130 => self.chr_upper = data & 0b11,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0b1000_0000 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5c00..=0x5fff => {
+                //in the nametable modes exram is being read by the ppu, outside a frame
+                //the write lands as zero
+                let index = (addr - 0x5c00) as usize;
+                match self.exram_mode {
+                    0 | 1 => self.exram[index] = if self.in_frame { data } else { 0 },
+                    2 => self.exram[index] = data,
+                    _ => {}
+                }
+            }
+            0x6000..=0xffff => {
+                let (rom, bank) = self.prg_bank(addr);
+                if !rom && self.prg_ram_writable() {
+                    let offset = bank * 0x2000 + (addr & 0x1fff) as usize;
+                    write_prg_ram(&mut self.prg_ram, offset, data);
+                }
+            }
+            _ => {}
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        if !self.fetching_background() {
+            return self.read_chr_bank(addr, self.last_chr_set_b);
+        }
+        if self.tile_split {
+            //the split has its own 4K page and y scroll, only the tile and plane come from
+            //the address the ppu built
+            let tile = (addr >> 4) & 0xff;
+            let plane = addr & 0b1000;
+            let index =
+                self.split_bank as usize * 0x1000 + (tile * 16 + plane + self.split_y % 8) as usize;
+            return self.chr[index % self.chr.len()];
+        }
+        if self.exram_mode == 1 {
+            let bank = (self.chr_upper as usize) << 6 | (self.tile_exram & 0b11_1111) as usize;
+            let index = bank * 0x1000 + (addr & 0x0fff) as usize;
+            return self.chr[index % self.chr.len()];
+        }
+        let set_b = if self.tall_sprites {
+            true
+        } else {
+            self.last_chr_set_b
+        };
+        self.read_chr_bank(addr, set_b)
+    }
+    fn read_sprite_chr(&self, addr: u16) -> u8 {
+        let set_b = if self.tall_sprites {
+            false
+        } else {
+            self.last_chr_set_b
+        };
+        self.read_chr_bank(addr, set_b)
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_bank(addr, self.last_chr_set_b) * 0x400 + (addr & 0x3ff) as usize;
+        let len = self.chr.len();
+        self.chr[index % len] = data;
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        let page = |n: u8| (self.nametable_mapping >> (n * 2)) & 1;
+        Mirroring::Custom([page(0), page(1), page(2), page(3)])
+    }
+    fn read_nametable(&mut self, addr: u16) -> Option<u8> {
+        self.idle_cycles = 0;
+        if addr == self.last_nametable_addr {
+            self.nametable_matches += 1;
+            if self.nametable_matches == 2 {
+                self.detect_scanline();
+            }
+        } else {
+            self.nametable_matches = 0;
+        }
+        self.last_nametable_addr = addr;
+        let offset = addr & 0x3ff;
+        let background = self.fetching_background();
+        if offset < 0x3c0 {
+            //columns 34 and up are the first two tiles of the next line and the dummy reads
+            let column = self.tile_count % 34;
+            let next_line = self.tile_count >= 34;
+            self.tile_count += 1;
+            self.tile_split = background && self.in_split(column);
+            if self.tile_split {
+                let line = self.scanline + next_line as u16;
+                self.split_y = (self.split_scroll as u16 + line) % 240;
+                return Some(self.exram[((self.split_y / 8) * 32 + column) as usize]);
+            }
+            self.tile_exram = self.exram[offset as usize];
+        } else if self.tile_split {
+            let column = (self.tile_count - 1) % 34;
+            let coarse_y = self.split_y / 8;
+            let attribute = self.exram[(0x3c0 + (coarse_y / 4) * 8 + column / 4) as usize];
+            let shift = (coarse_y & 0b10) << 1 | (column & 0b10);
+            return Some(((attribute >> shift) & 0b11) * 0x55);
+        } else if background && self.exram_mode == 1 {
+            return Some((self.tile_exram >> 6) * 0x55);
+        }
+        let table = (addr >> 10) & 0b11;
+        match (self.nametable_mapping >> (table * 2)) & 0b11 {
+            0 | 1 => None,
+            2 if self.exram_mode <= 1 => Some(self.exram[offset as usize]),
+            2 => Some(0),
+            _ if offset < 0x3c0 => Some(self.fill_tile),
+            _ => Some(self.fill_attribute * 0x55),
+        }
+    }
+    fn write_nametable(&mut self, addr: u16, data: u8) -> bool {
+        let table = (addr >> 10) & 0b11;
+        match (self.nametable_mapping >> (table * 2)) & 0b11 {
+            0 | 1 => false,
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[(addr & 0x3ff) as usize] = data;
+                }
+                true
+            }
+            _ => true,
+        }
+    }
+    fn audio_output(&self) -> f32 {
+        //the pulses go through the same kind of mixer as the apu's, pcm sits roughly
+        //where a full dmc would
+        pulse_mix(self.pulse1.unswept_output() + self.pulse2.unswept_output())
+            + tnd_mix(0, 0, self.pcm >> 1)
+    }
+    fn irq_pending(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+    fn ppu_address(&mut self, _addr: u16, _dot: u64) {
+        self.idle_cycles = 0;
+    }
+    fn snoop_ppu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x2000 => self.tall_sprites = data & 0b0010_0000 != 0,
+            0x2001 => {
+                self.rendering = data & 0b0001_1000 != 0;
+                if !self.rendering {
+                    self.in_frame = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    fn clock(&mut self) {
+        self.audio_cycles += 1;
+        if self.audio_cycles % 2 == 0 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        if self.audio_cycles == 7457 {
+            self.audio_cycles = 0;
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.envelope.clock();
+                pulse.length.clock();
+            }
+        }
+        if self.idle_cycles < 3 {
+            self.idle_cycles += 1;
+            if self.idle_cycles == 3 {
+                self.in_frame = false;
+                self.last_nametable_addr = 0;
+            }
+        }
+    }
+}
+impl Snapshot for MMC5 {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.exram);
+        s.u8(&mut self.prg_mode);
+        s.u8(&mut self.chr_mode);
+        s.bytes(&mut self.prg_ram_protect);
+        s.u8(&mut self.exram_mode);
+        s.u8(&mut self.nametable_mapping);
+        s.u8(&mut self.fill_tile);
+        s.u8_below(&mut self.fill_attribute, 4);
+        s.bytes(&mut self.prg_banks);
+        for value in self.chr_banks.iter_mut() {
+            s.u16(value);
+        }
+        s.u8(&mut self.chr_upper);
+        s.bool(&mut self.last_chr_set_b);
+        s.u8(&mut self.split_control);
+        s.u8(&mut self.split_scroll);
+        s.u8(&mut self.split_bank);
+        s.u8(&mut self.irq_compare);
+        s.bool(&mut self.irq_enabled);
+        s.bool(&mut self.irq_pending);
+        s.u8(&mut self.multiplicand);
+        s.u8(&mut self.multiplier);
+        self.pulse1.snapshot(s);
+        self.pulse2.snapshot(s);
+        s.u8(&mut self.pcm);
+        s.u16_range(&mut self.audio_cycles, 0..=7456);
+        s.bool(&mut self.tall_sprites);
+        s.bool(&mut self.rendering);
+        s.bool(&mut self.in_frame);
+        s.u16_range(&mut self.scanline, 0..=261);
+        s.u8(&mut self.idle_cycles);
+        s.u16(&mut self.last_nametable_addr);
+        s.u8_below(&mut self.nametable_matches, 3);
+        s.u16_range(&mut self.tile_count, 1..=0xff);
+        s.u8(&mut self.tile_exram);
+        s.bool(&mut self.tile_split);
+        s.u16_range(&mut self.split_y, 0..=239);
+    }
+}
@@ -0,0 +1,146 @@
+pub mod axrom;
+pub mod cnrom;
+pub mod fme7;
+pub mod mmc1;
+pub mod mmc3;
+pub mod mmc5;
+pub mod namco163;
+pub mod nrom;
+pub mod opll;
+pub mod uxrom;
+pub mod vrc6;
+pub mod vrc7;
+pub mod vrc_irq;
+use crate::rom::Mirroring;
+use crate::rom::{Rom, RomError, RomHeader};
+use crate::savestate::Snapshot;
+use axrom::AxROM;
+use cnrom::CNROM;
+use fme7::FME7;
+use mmc1::MMC1;
+use mmc3::MMC3;
+use mmc5::MMC5;
+use namco163::Namco163;
+use nrom::NROM;
+use std::cell::RefCell;
+use std::rc::Rc;
+use uxrom::UxROM;
+use vrc6::VRC6;
+use vrc7::VRC7;
+/*
+The cartridge side of both buses. The cpu sees $4020-$FFFF (expansion area,
+prg-ram at $6000-$7FFF and prg-rom at $8000-$FFFF), the ppu sees the pattern
+tables at $0000-$1FFF. Writes into rom space are how games talk to the
+mapper's bank registers.
+ */
+pub trait Mapper: Snapshot {
+    fn read_prg(&mut self, addr: u16) -> u8;
+    fn write_prg(&mut self, addr: u16, data: u8);
+    fn read_chr(&self, addr: u16) -> u8;
+    //sprite pattern fetches, MMC5 banks them separately from the background
+    fn read_sprite_chr(&self, addr: u16) -> u8 {
+        self.read_chr(addr)
+    }
+    //writes to chr-rom are ignored, as on the real boards
+    fn write_chr(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+    //nametable reads and writes at $2000-$2FFF, for cartridges that put their own memory
+    //there. None (or false) falls through to the console's vram through mirroring()
+    fn read_nametable(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+    fn write_nametable(&mut self, _addr: u16, _data: u8) -> bool {
+        false
+    }
+    //cpu writes to $2000-$2007, visible to the cartridge on the cpu bus
+    fn snoop_ppu_write(&mut self, _addr: u16, _data: u8) {}
+    //called once per cpu cycle
+    fn clock(&mut self) {}
+    //the cartridge's prg-ram as one flat block, starting with what $6000 shows at power on
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+    //expansion audio from the cartridge, on the same scale as APU::output
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+    //level of the cartridge's irq line, or'ed into the cpu irq with the apu sources
+    fn irq_pending(&self) -> bool {
+        false
+    }
+    //every pattern table address the ppu puts on its bus, stamped with PPU::dots,
+    //for mappers that watch the address lines (MMC3 counts rising edges of A12)
+    fn ppu_address(&mut self, _addr: u16, _dot: u64) {}
+}
+//pattern memory for a board: its chr-rom, or zeroed chr-ram of the size the header
+//asks for when there is no chr-rom
+pub fn chr_memory(chr_rom: Vec<u8>, chr_ram_size: usize) -> (Vec<u8>, bool) {
+    if chr_rom.is_empty() {
+        (vec![0; chr_ram_size.max(0x2000)], true)
+    } else {
+        (chr_rom, false)
+    }
+}
+//prg-ram for a board, the plain and then the battery backed ram the header asks for,
+//at least 8K when there is a trainer to load at $7000 and empty for boards without any.
+//Discrete boards like UxROM, CNROM and AxROM have none, so on those $6000-$7FFF reads 0
+pub fn prg_ram_memory(header: &RomHeader) -> Vec<u8> {
+    let size = header.prg_ram_size + header.prg_nvram_size;
+    if header.trainer {
+        vec![0; size.max(0x2000)]
+    } else {
+        vec![0; size]
+    }
+}
+//prg-ram accesses wrap around chips smaller than the window, boards without any
+//read 0 and ignore writes
+pub fn read_prg_ram(ram: &[u8], offset: usize) -> u8 {
+    if ram.is_empty() {
+        0
+    } else {
+        ram[offset % ram.len()]
+    }
+}
+pub fn write_prg_ram(ram: &mut [u8], offset: usize, data: u8) {
+    if !ram.is_empty() {
+        let len = ram.len();
+        ram[offset % len] = data;
+    }
+}
+//what a board returns from Mapper::prg_ram, None when it has no prg-ram
+pub fn prg_ram_slice(ram: &mut [u8]) -> Option<&mut [u8]> {
+    Some(ram).filter(|ram| !ram.is_empty())
+}
+//the bus and the ppu each hold a handle to the same cartridge
+pub type SharedMapper = Rc<RefCell<Box<dyn Mapper>>>;
+pub fn new(mut rom: Rom) -> Result<SharedMapper, RomError> {
+    let trainer = std::mem::take(&mut rom.trainer);
+    let mut mapper: Box<dyn Mapper> = match rom.header.mapper {
+        0 => Box::new(NROM::new(rom)),
+        1 => Box::new(MMC1::new(rom)),
+        2 => Box::new(UxROM::new(rom)),
+        3 => Box::new(CNROM::new(rom)),
+        4 => Box::new(MMC3::new(rom)),
+        5 => Box::new(MMC5::new(rom)),
+        7 => Box::new(AxROM::new(rom)),
+        19 => Box::new(Namco163::new(rom)),
+        24 | 26 => Box::new(VRC6::new(rom)),
+        69 => Box::new(FME7::new(rom)),
+        85 => Box::new(VRC7::new(rom)),
+        n => {
+            return Err(RomError::UnsupportedMapper {
+                offset: 6,
+                mapper: n,
+                submapper: rom.header.submapper,
+            })
+        }
+    };
+    //the trainer goes to $7000-$71FF before the cpu comes out of reset, prg_ram_memory
+    //gives every board with one at least 8K
+    if !trainer.is_empty() {
+        if let Some(ram) = mapper.prg_ram() {
+            ram[0x1000..0x1000 + trainer.len()].copy_from_slice(&trainer);
+        }
+    }
+    Ok(Rc::new(RefCell::new(mapper)))
+}
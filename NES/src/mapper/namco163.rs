@@ -0,0 +1,247 @@
+use crate::apu::PULSE_LEVEL;
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 19.
+$4800       sound data     read/write the sound ram byte at the address port
+$5000/$5800 irq counter    low 8 bits / E high 7 bits, writes acknowledge the irq
+$8000-$B800 chr banks      1K each for $0000-$1FFF
+$C000-$D800 nametables     values $E0 and up pick a vram page, lower values a 1K chr page
+$E000       prg bank       -Sxx xxxx  sound disable, 8K bank at $8000
+$E800       prg bank       8K at $A000
+$F000       prg bank       8K at $C000
+$F800       address port   Iaaa aaaa  auto increment, sound ram address
+$E000 is fixed to the last 8K bank.
+
+The irq counter counts up every cpu cycle while enabled and raises the irq at $7FFF.
+
+Sound is up to eight wavetable channels in 128 bytes of ram. The registers of channel n
+start at $40 + n*8:
+  +0 frequency low, +2 frequency mid, +4 LLLL LLHH  length (256 - L*4 samples), freq high
+  +1, +3, +5 phase, +6 wave address in 4 bit samples, +7 ---- VVVV  volume
+$7F bits 4-6 hold the number of enabled channels minus one, counted down from channel 7.
+The chip updates one channel every 15 cpu cycles and plays them one at a time, so each
+channel is heard for its share of the time.
+ */
+const CHANNEL_CYCLES: u8 = 15;
+//one channel alone at volume 15 and full wave swing (8 * 15) is roughly 2.8 apu pulses
+const N163_LEVEL: f32 = PULSE_LEVEL * 2.8 / 120.0;
+pub struct Namco163 {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub chr_banks: [u8; 8],
+    pub nametable_banks: [u8; 4],
+    pub prg_banks: [u8; 3],
+    pub sound_disabled: bool,
+    pub irq_counter: u16,
+    pub irq_flag: bool,
+    pub sound_ram: [u8; 0x80],
+    pub sound_address: u8,
+    pub auto_increment: bool,
+    pub channel_cycles: u8,
+    pub current_channel: usize,
+    pub channel_outputs: [i16; 8],
+}
+impl Namco163 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        Namco163 {
+            prg_rom: rom.prg_rom,
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            prg_ram: prg_ram_memory(&rom.header),
+            chr_banks: [0; 8],
+            nametable_banks: [0xe0, 0xe1, 0xe0, 0xe1],
+            prg_banks: [0; 3],
+            sound_disabled: false,
+            irq_counter: 0,
+            irq_flag: false,
+            sound_ram: [0; 0x80],
+            sound_address: 0,
+            auto_increment: false,
+            channel_cycles: 0,
+            current_channel: 7,
+            channel_outputs: [0; 8],
+        }
+    }
+    fn chr_address(&self, bank: u8, addr: u16) -> usize {
+        (bank as usize * 0x400 + (addr & 0x3ff) as usize) % self.chr.len()
+    }
+    fn active_channels(&self) -> usize {
+        ((self.sound_ram[0x7f] >> 4) & 0b111) as usize + 1
+    }
+    fn clock_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let ram = &mut self.sound_ram;
+        let frequency =
+            ram[base] as u32 | (ram[base + 2] as u32) << 8 | (ram[base + 4] as u32 & 0b11) << 16;
+        let phase =
+            ram[base + 1] as u32 | (ram[base + 3] as u32) << 8 | (ram[base + 5] as u32) << 16;
+        let length = 256 - (ram[base + 4] & 0b1111_1100) as u32;
+        let phase = (phase + frequency) % (length << 16);
+        ram[base + 1] = phase as u8;
+        ram[base + 3] = (phase >> 8) as u8;
+        ram[base + 5] = (phase >> 16) as u8;
+        let sample_address = (ram[base + 6] as u32 + (phase >> 16)) & 0xff;
+        let byte = ram[(sample_address >> 1) as usize];
+        let sample = if sample_address & 1 == 0 {
+            byte & 0x0f
+        } else {
+            byte >> 4
+        };
+        let volume = (ram[base + 7] & 0x0f) as i16;
+        self.channel_outputs[channel] = (sample as i16 - 8) * volume;
+    }
+}
+impl Mapper for Namco163 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4fff => {
+                let data = self.sound_ram[self.sound_address as usize];
+                if self.auto_increment {
+                    self.sound_address = (self.sound_address + 1) & 0x7f;
+                }
+                data
+            }
+            0x5000..=0x57ff => self.irq_counter as u8,
+            0x5800..=0x5fff => (self.irq_counter >> 8) as u8,
+            0x6000..=0x7fff => read_prg_ram(&self.prg_ram, (addr - 0x6000) as usize),
+            0x8000..=0xffff => {
+                let bank = match addr {
+                    0x8000..=0x9fff => self.prg_banks[0] as usize,
+                    0xa000..=0xbfff => self.prg_banks[1] as usize,
+                    0xc000..=0xdfff => self.prg_banks[2] as usize,
+                    _ => self.prg_rom.len() / 0x2000 - 1,
+                };
+                self.prg_rom[(bank * 0x2000 + (addr & 0x1fff) as usize) % self.prg_rom.len()]
+            }
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4fff => {
+                self.sound_ram[self.sound_address as usize] = data;
+                if self.auto_increment {
+                    self.sound_address = (self.sound_address + 1) & 0x7f;
+                }
+            }
+            0x5000..=0x57ff => {
+                self.irq_counter = (self.irq_counter & 0xff00) | data as u16;
+                self.irq_flag = false;
+            }
+            0x5800..=0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | (data as u16) << 8;
+                self.irq_flag = false;
+            }
+            0x6000..=0x7fff => write_prg_ram(&mut self.prg_ram, (addr - 0x6000) as usize, data),
+            0x8000..=0xbfff => self.chr_banks[((addr - 0x8000) / 0x800) as usize] = data,
+            0xc000..=0xdfff => self.nametable_banks[((addr - 0xc000) / 0x800) as usize] = data,
+            0xe000..=0xe7ff => {
+                self.prg_banks[0] = data & 0b0011_1111;
+                self.sound_disabled = data & 0b0100_0000 != 0;
+            }
+            0xe800..=0xefff => self.prg_banks[1] = data & 0b0011_1111,
+            0xf000..=0xf7ff => self.prg_banks[2] = data & 0b0011_1111,
+            0xf800..=0xffff => {
+                self.sound_address = data & 0x7f;
+                self.auto_increment = data & 0b1000_0000 != 0;
+            }
+            _ => {}
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.chr_address(self.chr_banks[(addr >> 10) as usize], addr)]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let index = self.chr_address(self.chr_banks[(addr >> 10) as usize], addr);
+            self.chr[index] = data;
+        }
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        let page = |n: usize| self.nametable_banks[n] & 1;
+        Mirroring::Custom([page(0), page(1), page(2), page(3)])
+    }
+    fn read_nametable(&mut self, addr: u16) -> Option<u8> {
+        let bank = self.nametable_banks[((addr >> 10) & 0b11) as usize];
+        if bank >= 0xe0 {
+            None
+        } else {
+            Some(self.chr[self.chr_address(bank, addr)])
+        }
+    }
+    fn write_nametable(&mut self, addr: u16, data: u8) -> bool {
+        //nametables mapped to chr-rom swallow writes
+        let bank = self.nametable_banks[((addr >> 10) & 0b11) as usize];
+        if bank < 0xe0 && self.chr_is_ram {
+            let index = self.chr_address(bank, addr);
+            self.chr[index] = data;
+        }
+        bank < 0xe0
+    }
+    fn clock(&mut self) {
+        if self.irq_counter & 0x8000 != 0 && self.irq_counter & 0x7fff != 0x7fff {
+            self.irq_counter += 1;
+            if self.irq_counter & 0x7fff == 0x7fff {
+                self.irq_flag = true;
+            }
+        }
+        if self.sound_disabled {
+            return;
+        }
+        self.channel_cycles += 1;
+        if self.channel_cycles == CHANNEL_CYCLES {
+            self.channel_cycles = 0;
+            self.clock_channel(self.current_channel);
+            let first = 8 - self.active_channels();
+            self.current_channel = if self.current_channel <= first {
+                7
+            } else {
+                self.current_channel - 1
+            };
+        }
+    }
+    fn audio_output(&self) -> f32 {
+        if self.sound_disabled {
+            return 0.0;
+        }
+        let active = self.active_channels();
+        let total: i16 = self.channel_outputs[8 - active..].iter().sum();
+        total as f32 / active as f32 * N163_LEVEL
+    }
+    fn irq_pending(&self) -> bool {
+        self.irq_flag
+    }
+}
+impl Snapshot for Namco163 {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.chr_banks);
+        s.bytes(&mut self.nametable_banks);
+        s.bytes(&mut self.prg_banks);
+        s.bool(&mut self.sound_disabled);
+        s.u16(&mut self.irq_counter);
+        s.bool(&mut self.irq_flag);
+        s.bytes(&mut self.sound_ram);
+        s.u8_below(&mut self.sound_address, 0x80);
+        s.bool(&mut self.auto_increment);
+        s.u8_below(&mut self.channel_cycles, CHANNEL_CYCLES);
+        s.usize_below(&mut self.current_channel, 8);
+        for output in self.channel_outputs.iter_mut() {
+            s.i16(output);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 0, no banking. 16K of prg-rom is mirrored into both halves of
+$8000-$FFFF, 32K fills it. Family Basic boards add prg-ram at $6000, a few
+homebrew boards swap the chr-rom for chr-ram.
+ */
+pub struct NROM {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub mirroring: Mirroring,
+}
+impl NROM {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        NROM {
+            prg_rom: rom.prg_rom,
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            prg_ram: prg_ram_memory(&rom.header),
+            mirroring: rom.header.mirroring,
+        }
+    }
+}
+impl Mapper for NROM {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff => read_prg_ram(&self.prg_ram, (addr - 0x6000) as usize),
+            0x8000..=0xffff => {
+                let addr = (addr - 0x8000) as usize % self.prg_rom.len();
+                self.prg_rom[addr]
+            }
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7fff = addr {
+            write_prg_ram(&mut self.prg_ram, (addr - 0x6000) as usize, data);
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[addr as usize]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        self.chr[addr as usize] = data;
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+impl Snapshot for NROM {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use crate::savestate::{Snapshot, StateStream};
+/*
+The VRC7's sound core, a cut down YM2413 (OPLL) with six two-operator FM channels.
+$9010 register select, $9030 register data
+  $00-$07  custom instrument, same layout as the built in patches below
+  $10-$15  frequency low 8 bits for channels 0-5
+  $20-$25  --ST BBBF  sustain, key on, block (octave), frequency bit 8
+  $30-$35  IIII VVVV  instrument (0: custom), volume (attenuation in 3dB steps)
+
+Instrument bytes
+  0/1  AVEK MMMM  modulator/carrier: tremolo, vibrato, sustained envelope, rate key scale, multiplier
+  2    KKTT TTTT  modulator key scale level, modulator total level (0.75dB steps)
+  3    KK-Q WFFF  carrier key scale level, carrier/modulator half sine waves, feedback
+  4/5  AAAA DDDD  attack and decay rates
+  6/7  SSSS RRRR  sustain level (3dB steps) and release rate
+
+The chip produces a sample every 36 cpu cycles (49.7kHz). This is a floating point model:
+phases are fractions of a cycle, envelopes are attenuations in dB.
+ */
+pub const SAMPLE_CYCLES: u8 = 36;
+const SAMPLE_RATE: f32 = 1_789_773.0 / SAMPLE_CYCLES as f32;
+const MAX_ATTENUATION: f32 = 48.0;
+#[rustfmt::skip]
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xe8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0d, 0xd8, 0xf6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xfa, 0xb2, 0x20, 0x12],
+    [0x31, 0x61, 0x0c, 0x07, 0xa8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1e, 0x06, 0xe1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xa3, 0xe2, 0xf4, 0xf4],
+    [0x21, 0x61, 0x1d, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xa2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xb5, 0x01, 0x0f, 0x0f, 0xa8, 0xa5, 0x51, 0x02],
+    [0x17, 0xc1, 0x24, 0x07, 0xf8, 0xf8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xd3, 0x05, 0xc9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0c, 0x00, 0x94, 0xc0, 0x33, 0xf6],
+    [0x21, 0x72, 0x0d, 0x00, 0xc1, 0xd5, 0x56, 0x06],
+];
+const MULTIPLIERS: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+//key scale attenuation in dB for the top 4 frequency bits at block 7
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+    42.0,
+];
+#[derive(PartialEq, Clone, Copy)]
+pub enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+#[derive(Clone, Copy)]
+pub struct Operator {
+    pub phase: f32,
+    pub attenuation: f32,
+    pub state: EnvelopeState,
+    pub output: f32,
+}
+impl Default for Operator {
+    fn default() -> Self {
+        Operator::new()
+    }
+}
+impl Operator {
+    pub fn new() -> Self {
+        Operator {
+            phase: 0.0,
+            attenuation: MAX_ATTENUATION,
+            state: EnvelopeState::Off,
+            output: 0.0,
+        }
+    }
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+    fn key_off(&mut self) {
+        if self.state != EnvelopeState::Off {
+            self.state = EnvelopeState::Release;
+        }
+    }
+    //moves the envelope on by one sample. rate is the 0-63 effective rate
+    fn clock_envelope(&mut self, patch: &[u8; 8], op: usize, key_scale_rate: u8, sustain: bool) {
+        let sustained = patch[op] & 0b0010_0000 != 0;
+        let attack = patch[4 + op] >> 4;
+        let decay = patch[4 + op] & 0x0f;
+        let sustain_level = (patch[6 + op] >> 4) as f32 * 3.0;
+        let release = patch[6 + op] & 0x0f;
+        let rate = |r: u8| {
+            if r == 0 {
+                0
+            } else {
+                (r * 4 + key_scale_rate).min(63)
+            }
+        };
+        match self.state {
+            EnvelopeState::Attack => {
+                let rate = rate(attack);
+                if rate >= 60 {
+                    self.attenuation = 0.0;
+                } else if rate > 0 {
+                    //exponential approach, fastest attack is about 0.1ms
+                    let time = 1.7 * 2f32.powf(-(rate as f32 - 4.0) / 4.0);
+                    self.attenuation -= (self.attenuation + 1.0) * 4.0 / (time * SAMPLE_RATE);
+                }
+                if self.attenuation <= 0.0 {
+                    self.attenuation = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                self.attenuation += decay_step(rate(decay));
+                if self.attenuation >= sustain_level {
+                    self.attenuation = sustain_level;
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+            EnvelopeState::Sustain => {
+                //percussive instruments keep fading at the release rate while keyed on
+                if !sustained {
+                    self.attenuation += decay_step(rate(release));
+                }
+            }
+            EnvelopeState::Release => {
+                let release = if sustain {
+                    5
+                } else if sustained {
+                    release
+                } else {
+                    7
+                };
+                self.attenuation += decay_step(rate(release));
+            }
+            EnvelopeState::Off => {}
+        }
+        if self.state != EnvelopeState::Attack && self.attenuation >= MAX_ATTENUATION {
+            self.attenuation = MAX_ATTENUATION;
+            self.state = EnvelopeState::Off;
+        }
+    }
+}
+//dB per sample for a decay or release rate
+fn decay_step(rate: u8) -> f32 {
+    if rate == 0 {
+        0.0
+    } else {
+        //the slowest rate takes about 10 seconds over the whole range
+        let time = 20.0 * 2f32.powf(-(rate as f32 - 4.0) / 4.0);
+        MAX_ATTENUATION / (time * SAMPLE_RATE)
+    }
+}
+pub struct Channel {
+    pub frequency: u16,
+    pub block: u8,
+    pub key_on: bool,
+    pub sustain: bool,
+    pub instrument: u8,
+    pub volume: u8,
+    pub modulator: Operator,
+    pub carrier: Operator,
+    pub feedback: [f32; 2],
+}
+impl Default for Channel {
+    fn default() -> Self {
+        Channel::new()
+    }
+}
+impl Channel {
+    pub fn new() -> Self {
+        Channel {
+            frequency: 0,
+            block: 0,
+            key_on: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+            feedback: [0.0; 2],
+        }
+    }
+}
+pub struct OPLL {
+    pub address: u8,
+    pub custom: [u8; 8],
+    pub channels: [Channel; 6],
+    pub cycles: u8,
+    pub tremolo_phase: f32,
+    pub vibrato_phase: f32,
+    pub output: f32,
+}
+impl Default for OPLL {
+    fn default() -> Self {
+        OPLL::new()
+    }
+}
+impl OPLL {
+    pub fn new() -> Self {
+        OPLL {
+            address: 0,
+            custom: [0; 8],
+            channels: std::array::from_fn(|_| Channel::new()),
+            cycles: 0,
+            tremolo_phase: 0.0,
+            vibrato_phase: 0.0,
+            output: 0.0,
+        }
+    }
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data;
+    }
+    pub fn write_data(&mut self, data: u8) {
+        let index = (self.address & 0x0f) as usize;
+        match self.address {
+            0x00..=0x07 => self.custom[index] = data,
+            0x10..=0x15 => {
+                let channel = &mut self.channels[index];
+                channel.frequency = (channel.frequency & 0x100) | data as u16;
+            }
+            0x20..=0x25 => {
+                let channel = &mut self.channels[index];
+                channel.frequency = (channel.frequency & 0xff) | (data as u16 & 1) << 8;
+                channel.block = (data >> 1) & 0b111;
+                channel.sustain = data & 0b10_0000 != 0;
+                let key_on = data & 0b1_0000 != 0;
+                if key_on && !channel.key_on {
+                    channel.modulator.key_on();
+                    channel.carrier.key_on();
+                } else if !key_on && channel.key_on {
+                    channel.modulator.key_off();
+                    channel.carrier.key_off();
+                }
+                channel.key_on = key_on;
+            }
+            0x30..=0x35 => {
+                let channel = &mut self.channels[index];
+                channel.instrument = data >> 4;
+                channel.volume = data & 0x0f;
+            }
+            _ => {}
+        }
+    }
+    pub fn reset(&mut self) {
+        *self = OPLL::new();
+    }
+    //called once per cpu cycle
+    pub fn clock(&mut self) {
+        self.cycles += 1;
+        if self.cycles == SAMPLE_CYCLES {
+            self.cycles = 0;
+            self.output = self.sample();
+        }
+    }
+    fn sample(&mut self) -> f32 {
+        //tremolo is 4.8dB deep at 3.7Hz, vibrato about 7 cents at 6.4Hz
+        self.tremolo_phase = (self.tremolo_phase + 3.7 / SAMPLE_RATE).fract();
+        self.vibrato_phase = (self.vibrato_phase + 6.4 / SAMPLE_RATE).fract();
+        let tremolo = (1.0 - (self.tremolo_phase * std::f32::consts::TAU).cos()) * 2.4;
+        let vibrato = 1.0 + (self.vibrato_phase * std::f32::consts::TAU).sin() * 0.004;
+        let custom = self.custom;
+        let mut total = 0.0;
+        for channel in self.channels.iter_mut() {
+            let patch = if channel.instrument == 0 {
+                &custom
+            } else {
+                &PATCHES[channel.instrument as usize - 1]
+            };
+            let key_scale = KEY_SCALE_LEVELS[(channel.frequency >> 5) as usize]
+                - 6.0 * (7 - channel.block) as f32;
+            let key_scale = key_scale.max(0.0);
+            let key_scale_level = |bits: u8| match bits {
+                0 => 0.0,
+                1 => key_scale / 2.0,
+                2 => key_scale,
+                _ => key_scale * 2.0,
+            };
+            //modulator, with feedback from its own last two outputs
+            let modulator = &mut channel.modulator;
+            let feedback = patch[3] & 0b111;
+            let feedback = if feedback == 0 {
+                0.0
+            } else {
+                (channel.feedback[0] + channel.feedback[1]) / 2.0 * 2f32.powi(feedback as i32)
+                    / 64.0
+            };
+            let attenuation = modulator.attenuation
+                + (patch[2] & 0b0011_1111) as f32 * 0.75
+                + key_scale_level(patch[2] >> 6)
+                + if patch[0] & 0b1000_0000 != 0 {
+                    tremolo
+                } else {
+                    0.0
+                };
+            modulator.output = operator_output(
+                modulator.phase + feedback,
+                attenuation,
+                patch[3] & 0b0000_1000 != 0,
+            );
+            if modulator.state == EnvelopeState::Off {
+                modulator.output = 0.0;
+            }
+            channel.feedback = [channel.feedback[1], modulator.output];
+            //carrier, phase modulated by the modulator
+            let carrier = &mut channel.carrier;
+            let attenuation = carrier.attenuation
+                + channel.volume as f32 * 3.0
+                + key_scale_level(patch[3] >> 6)
+                + if patch[1] & 0b1000_0000 != 0 {
+                    tremolo
+                } else {
+                    0.0
+                };
+            carrier.output = operator_output(
+                carrier.phase + modulator.output * 2.0,
+                attenuation,
+                patch[3] & 0b0001_0000 != 0,
+            );
+            if carrier.state != EnvelopeState::Off {
+                total += carrier.output;
+            }
+            //advance phases and envelopes
+            let base = channel.frequency as f32 * 2f32.powi(channel.block as i32) / 524288.0;
+            let key_scale_rate = |flags: u8| {
+                if flags & 0b0001_0000 != 0 {
+                    channel.block * 2 + (channel.frequency >> 8) as u8
+                } else {
+                    channel.block / 2
+                }
+            };
+            for (op, operator) in [&mut channel.modulator, &mut channel.carrier]
+                .into_iter()
+                .enumerate()
+            {
+                let mut step = base * MULTIPLIERS[(patch[op] & 0x0f) as usize];
+                if patch[op] & 0b0100_0000 != 0 {
+                    step *= vibrato;
+                }
+                operator.phase = (operator.phase + step).fract();
+                operator.clock_envelope(patch, op, key_scale_rate(patch[op]), channel.sustain);
+            }
+        }
+        total
+    }
+}
+//a sine (or a half sine with the negative half cut) at an attenuation in dB
+fn operator_output(phase: f32, attenuation: f32, half_sine: bool) -> f32 {
+    let wave = (phase * std::f32::consts::TAU).sin();
+    if (half_sine && wave < 0.0) || attenuation >= MAX_ATTENUATION {
+        0.0
+    } else {
+        wave * 10f32.powf(-attenuation / 20.0)
+    }
+}
+impl Snapshot for OPLL {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u8(&mut self.address);
+        s.bytes(&mut self.custom);
+        for channel in self.channels.iter_mut() {
+            channel.snapshot(s);
+        }
+        s.u8_below(&mut self.cycles, SAMPLE_CYCLES);
+        s.f32(&mut self.tremolo_phase);
+        s.f32(&mut self.vibrato_phase);
+        s.f32(&mut self.output);
+    }
+}
+impl Snapshot for Channel {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u16(&mut self.frequency);
+        s.u8_below(&mut self.block, 8);
+        s.bool(&mut self.key_on);
+        s.bool(&mut self.sustain);
+        s.u8_below(&mut self.instrument, 16);
+        s.u8(&mut self.volume);
+        self.modulator.snapshot(s);
+        self.carrier.snapshot(s);
+        s.f32(&mut self.feedback[0]);
+        s.f32(&mut self.feedback[1]);
+    }
+}
+impl Snapshot for Operator {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        let mut state = self.state as u8;
+        s.f32(&mut self.phase);
+        s.f32(&mut self.attenuation);
+        s.u8(&mut state);
+        s.f32(&mut self.output);
+        self.state = match state {
+            0 => EnvelopeState::Attack,
+            1 => EnvelopeState::Decay,
+            2 => EnvelopeState::Sustain,
+            3 => EnvelopeState::Release,
+            _ => EnvelopeState::Off,
+        };
+    }
+}
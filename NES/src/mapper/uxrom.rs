@@ -0,0 +1,73 @@
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 2. Any write to $8000-$FFFF selects the 16K bank at $8000, the last
+bank is fixed at $C000. Boards almost always carry 8K of chr-ram.
+ */
+pub struct UxROM {
+    pub prg_rom: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub mirroring: Mirroring,
+    pub prg_bank: u8,
+}
+impl UxROM {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        UxROM {
+            prg_rom: rom.prg_rom,
+            prg_ram: prg_ram_memory(&rom.header),
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            mirroring: rom.header.mirroring,
+            prg_bank: 0,
+        }
+    }
+}
+impl Mapper for UxROM {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x6000..=0x7fff => return read_prg_ram(&self.prg_ram, (addr - 0x6000) as usize),
+            0x8000..=0xbfff => self.prg_bank as usize,
+            0xc000..=0xffff => self.prg_rom.len() / 0x4000 - 1,
+            _ => return 0,
+        };
+        self.prg_rom[(bank * 0x4000 + (addr & 0x3fff) as usize) % self.prg_rom.len()]
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7fff = addr {
+            write_prg_ram(&mut self.prg_ram, (addr - 0x6000) as usize, data);
+        } else if addr >= 0x8000 {
+            self.prg_bank = data;
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[addr as usize]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        self.chr[addr as usize] = data;
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+impl Snapshot for UxROM {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.u8(&mut self.prg_bank);
+    }
+}
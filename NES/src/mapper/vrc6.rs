@@ -0,0 +1,339 @@
+use crate::apu::PULSE_LEVEL;
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mappers 24 (VRC6a) and 26 (VRC6b, the same chip with A0 and A1 swapped).
+$8000-$8003 prg bank    16K at $8000
+$9000-$9002 pulse 1     see below
+$9003       frequency   ---- -ABH  period shifted right by 8 (B) or 4 (A), halt all
+$A000-$A002 pulse 2
+$B000-$B002 sawtooth
+$B003       control     R--- MMPP  prg-ram enable, mirroring, chr layout
+$C000-$C003 prg bank    8K at $C000
+$D000-$E003 chr banks   R0-R7
+$F000-$F002 irq         latch, control, acknowledge
+$E000 is fixed to the last 8K bank.
+
+Chr layouts: 0: eight 1K banks, 1: four 2K banks from R0-R3,
+2 and 3: 1K banks from R0-R3 at $0000 then 2K banks from R4-R5 at $1000
+
+Pulse   $x000 GDDD VVVV  ignore duty (always on), duty (D+1)/16, volume
+        $x001 PPPP PPPP  period low
+        $x002 E--- PPPP  enable, period high
+Sawtooth $B000 --AA AAAA accumulator rate
+        $B001/$B002      period, as the pulses
+The sawtooth adds its rate to an accumulator on every other step and resets after
+seven adds, the top five bits are the output.
+ */
+//a vrc6 pulse at volume 15 measures about 1.5 times an apu pulse, per step of 15
+const VRC6_LEVEL: f32 = PULSE_LEVEL * 1.5 / 15.0;
+pub struct VRC6Pulse {
+    pub volume: u8,
+    pub duty: u8,
+    pub ignore_duty: bool,
+    pub period: u16,
+    pub enabled: bool,
+    pub timer: u16,
+    pub step: u8,
+}
+impl Default for VRC6Pulse {
+    fn default() -> Self {
+        VRC6Pulse::new()
+    }
+}
+impl VRC6Pulse {
+    pub fn new() -> Self {
+        VRC6Pulse {
+            volume: 0,
+            duty: 0,
+            ignore_duty: false,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 15,
+        }
+    }
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.ignore_duty = data & 0b1000_0000 != 0;
+                self.duty = (data >> 4) & 0b111;
+                self.volume = data & 0b1111;
+            }
+            1 => self.period = (self.period & 0x0f00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | (data as u16 & 0x0f) << 8;
+                self.enabled = data & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+    pub fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = if self.step == 0 { 15 } else { self.step - 1 };
+        } else {
+            self.timer -= 1;
+        }
+    }
+    pub fn output(&self) -> u8 {
+        if self.enabled && (self.ignore_duty || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+pub struct VRC6Sawtooth {
+    pub rate: u8,
+    pub period: u16,
+    pub enabled: bool,
+    pub timer: u16,
+    pub step: u8,
+    pub accumulator: u8,
+}
+impl Default for VRC6Sawtooth {
+    fn default() -> Self {
+        VRC6Sawtooth::new()
+    }
+}
+impl VRC6Sawtooth {
+    pub fn new() -> Self {
+        VRC6Sawtooth {
+            rate: 0,
+            period: 0,
+            enabled: false,
+            timer: 0,
+            step: 0,
+            accumulator: 0,
+        }
+    }
+    pub fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0b0011_1111,
+            1 => self.period = (self.period & 0x0f00) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00ff) | (data as u16 & 0x0f) << 8;
+                self.enabled = data & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+    pub fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step % 2 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+    pub fn output(&self) -> u8 {
+        if self.enabled {
+            self.accumulator >> 3
+        } else {
+            0
+        }
+    }
+}
+pub struct VRC6 {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub swap_address_lines: bool,
+    pub prg_bank_16: u8,
+    pub prg_bank_8: u8,
+    pub chr_banks: [u8; 8],
+    pub control: u8,
+    pub irq: VrcIrq,
+    pub halt_audio: bool,
+    pub frequency_shift: u8,
+    pub pulse1: VRC6Pulse,
+    pub pulse2: VRC6Pulse,
+    pub sawtooth: VRC6Sawtooth,
+}
+impl VRC6 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        VRC6 {
+            swap_address_lines: rom.header.mapper == 26,
+            prg_rom: rom.prg_rom,
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            prg_ram: prg_ram_memory(&rom.header),
+            prg_bank_16: 0,
+            prg_bank_8: 0,
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::new(),
+            halt_audio: false,
+            frequency_shift: 0,
+            pulse1: VRC6Pulse::new(),
+            pulse2: VRC6Pulse::new(),
+            sawtooth: VRC6Sawtooth::new(),
+        }
+    }
+    fn prg_address(&self, addr: u16) -> usize {
+        let offset = match addr {
+            0x8000..=0xbfff => self.prg_bank_16 as usize * 0x4000 + (addr & 0x3fff) as usize,
+            0xc000..=0xdfff => self.prg_bank_8 as usize * 0x2000 + (addr & 0x1fff) as usize,
+            _ => self.prg_rom.len() - 0x2000 + (addr & 0x1fff) as usize,
+        };
+        offset % self.prg_rom.len()
+    }
+    fn chr_address(&self, addr: u16) -> usize {
+        let slot = (addr >> 10) as usize;
+        let (bank, size) = match (self.control & 0b11, slot) {
+            (0, _) => (self.chr_banks[slot], 0x400),
+            (1, _) => (self.chr_banks[slot / 2], 0x800),
+            (_, 0..=3) => (self.chr_banks[slot], 0x400),
+            (_, _) => (self.chr_banks[4 + (slot - 4) / 2], 0x800),
+        };
+        (bank as usize * size + (addr as usize % size)) % self.chr.len()
+    }
+}
+impl Mapper for VRC6 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.control & 0b1000_0000 != 0 => {
+                read_prg_ram(&self.prg_ram, (addr - 0x6000) as usize)
+            }
+            0x8000..=0xffff => self.prg_rom[self.prg_address(addr)],
+            _ => 0,
+        }
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        let addr = if self.swap_address_lines {
+            (addr & 0xfffc) | (addr & 1) << 1 | (addr >> 1) & 1
+        } else {
+            addr
+        };
+        let register = addr & 0b11;
+        match (addr & 0xf000, register) {
+            (0x6000 | 0x7000, _) => {
+                if self.control & 0b1000_0000 != 0 {
+                    write_prg_ram(&mut self.prg_ram, (addr - 0x6000) as usize, data);
+                }
+            }
+            (0x8000, _) => self.prg_bank_16 = data & 0x0f,
+            (0x9000, 3) => {
+                self.halt_audio = data & 0b001 != 0;
+                self.frequency_shift = if data & 0b100 != 0 {
+                    8
+                } else if data & 0b010 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            (0x9000, _) => self.pulse1.write(register, data),
+            (0xa000, 3) => {}
+            (0xa000, _) => self.pulse2.write(register, data),
+            (0xb000, 3) => self.control = data,
+            (0xb000, _) => self.sawtooth.write(register, data),
+            (0xc000, _) => self.prg_bank_8 = data & 0x1f,
+            (0xd000, _) => self.chr_banks[register as usize] = data,
+            (0xe000, _) => self.chr_banks[4 + register as usize] = data,
+            (0xf000, 0) => self.irq.latch = data,
+            (0xf000, 1) => self.irq.write_control(data),
+            (0xf000, 2) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.chr_address(addr)]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let index = self.chr_address(addr);
+            self.chr[index] = data;
+        }
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        match (self.control >> 2) & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+    fn clock(&mut self) {
+        self.irq.clock();
+        if !self.halt_audio {
+            self.pulse1.clock(self.frequency_shift);
+            self.pulse2.clock(self.frequency_shift);
+            self.sawtooth.clock(self.frequency_shift);
+        }
+    }
+    fn audio_output(&self) -> f32 {
+        let total = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+        total as f32 * VRC6_LEVEL
+    }
+    fn irq_pending(&self) -> bool {
+        self.irq.flag
+    }
+}
+impl Snapshot for VRC6 {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.u8(&mut self.prg_bank_16);
+        s.u8(&mut self.prg_bank_8);
+        s.bytes(&mut self.chr_banks);
+        s.u8(&mut self.control);
+        self.irq.snapshot(s);
+        s.bool(&mut self.halt_audio);
+        s.u8_range(&mut self.frequency_shift, 0..=8);
+        self.pulse1.snapshot(s);
+        self.pulse2.snapshot(s);
+        self.sawtooth.snapshot(s);
+    }
+}
+impl Snapshot for VRC6Pulse {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u8(&mut self.volume);
+        s.u8(&mut self.duty);
+        s.bool(&mut self.ignore_duty);
+        s.u16(&mut self.period);
+        s.bool(&mut self.enabled);
+        s.u16(&mut self.timer);
+        s.u8(&mut self.step);
+    }
+}
+impl Snapshot for VRC6Sawtooth {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u8(&mut self.rate);
+        s.u16(&mut self.period);
+        s.bool(&mut self.enabled);
+        s.u16(&mut self.timer);
+        s.u8_below(&mut self.step, 14);
+        s.u8(&mut self.accumulator);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::apu::PULSE_LEVEL;
+use crate::mapper::opll::OPLL;
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::mapper::{
+    chr_memory, prg_ram_memory, prg_ram_slice, read_prg_ram, write_prg_ram, Mapper,
+};
+use crate::rom::Mirroring;
+use crate::rom::Rom;
+use crate::savestate::{Snapshot, StateStream};
+/*
+Mapper 85. Boards wire the second register of each pair to either A3 or A4, both are
+accepted here.
+$8000       prg bank    8K at $8000
+$8010/$8008 prg bank    8K at $A000
+$9000       prg bank    8K at $C000
+$9010/$9030 sound       register select, register data (see opll.rs)
+$A000-$D010 chr banks   eight 1K banks, two per $1000 of address space
+$E000       control     RS-- --MM  prg-ram enable, sound silence and reset, mirroring
+$E010-$F010 irq         latch, control, acknowledge
+$E000 is fixed to the last 8K bank.
+ */
+//the opll's dac is quiet next to the apu, a full sine peaks near a pulse at volume 15
+const VRC7_LEVEL: f32 = PULSE_LEVEL;
+pub struct VRC7 {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub prg_banks: [u8; 3],
+    pub chr_banks: [u8; 8],
+    pub control: u8,
+    pub irq: VrcIrq,
+    pub opll: OPLL,
+}
+impl VRC7 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_is_ram) = chr_memory(rom.chr_rom, rom.header.chr_ram_size);
+        VRC7 {
+            prg_rom: rom.prg_rom,
+            chr: chr,
+            chr_is_ram: chr_is_ram,
+            prg_ram: prg_ram_memory(&rom.header),
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::new(),
+            opll: OPLL::new(),
+        }
+    }
+    fn chr_address(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+        (bank * 0x400 + (addr & 0x3ff) as usize) % self.chr.len()
+    }
+}
+impl Mapper for VRC7 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x6000..=0x7fff if self.control & 0b1000_0000 != 0 => {
+                return read_prg_ram(&self.prg_ram, (addr - 0x6000) as usize);
+            }
+            0x8000..=0x9fff => self.prg_banks[0] as usize,
+            0xa000..=0xbfff => self.prg_banks[1] as usize,
+            0xc000..=0xdfff => self.prg_banks[2] as usize,
+            0xe000..=0xffff => self.prg_rom.len() / 0x2000 - 1,
+            _ => return 0,
+        };
+        self.prg_rom[(bank * 0x2000 + (addr & 0x1fff) as usize) % self.prg_rom.len()]
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        //fold A3 onto A4 so both board wirings decode alike
+        let high = addr & 0b0001_1000 != 0;
+        match (addr & 0xf000, high) {
+            (0x6000 | 0x7000, _) => {
+                if self.control & 0b1000_0000 != 0 {
+                    write_prg_ram(&mut self.prg_ram, (addr - 0x6000) as usize, data);
+                }
+            }
+            (0x8000, false) => self.prg_banks[0] = data & 0b0011_1111,
+            (0x8000, true) => self.prg_banks[1] = data & 0b0011_1111,
+            (0x9000, false) => self.prg_banks[2] = data & 0b0011_1111,
+            (0x9000, true) => {
+                if addr & 0b0010_0000 != 0 {
+                    self.opll.write_data(data);
+                } else {
+                    self.opll.write_address(data);
+                }
+            }
+            (0xa000..=0xd000, _) => {
+                let index = ((addr - 0xa000) >> 12) as usize * 2 + high as usize;
+                self.chr_banks[index] = data;
+            }
+            (0xe000, false) => {
+                if data & 0b0100_0000 != 0 {
+                    self.opll.reset();
+                }
+                self.control = data;
+            }
+            (0xe000, true) => self.irq.latch = data,
+            (0xf000, false) => self.irq.write_control(data),
+            (0xf000, true) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.chr_address(addr)]
+    }
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let index = self.chr_address(addr);
+            self.chr[index] = data;
+        }
+    }
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        prg_ram_slice(&mut self.prg_ram)
+    }
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+    fn clock(&mut self) {
+        self.irq.clock();
+        if self.control & 0b0100_0000 == 0 {
+            self.opll.clock();
+        }
+    }
+    fn audio_output(&self) -> f32 {
+        if self.control & 0b0100_0000 != 0 {
+            0.0
+        } else {
+            self.opll.output * VRC7_LEVEL
+        }
+    }
+    fn irq_pending(&self) -> bool {
+        self.irq.flag
+    }
+}
+impl Snapshot for VRC7 {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.prg_ram);
+        if self.chr_is_ram {
+            s.bytes(&mut self.chr);
+        }
+        s.bytes(&mut self.prg_banks);
+        s.bytes(&mut self.chr_banks);
+        s.u8(&mut self.control);
+        self.irq.snapshot(s);
+        self.opll.snapshot(s);
+    }
+}
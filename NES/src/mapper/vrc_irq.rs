@@ -0,0 +1,86 @@
+use crate::savestate::{Snapshot, StateStream};
+/*
+The irq counter shared by Konami's VRC boards (VRC4, VRC6, VRC7).
+latch      counter reload value
+control    ---- -MEA  mode (1: cpu cycles, 0: scanlines), enable, enable after acknowledge
+acknowledge           clears the irq and copies A into E
+
+The 8 bit counter counts up and reloads from the latch when it overflows, raising the
+irq. In scanline mode a prescaler steps it every 113.67 cpu cycles (341 ppu dots) so the
+chip can count lines without watching the ppu.
+ */
+pub struct VrcIrq {
+    pub latch: u8,
+    pub counter: u8,
+    pub prescaler: i16,
+    pub enabled: bool,
+    pub enable_after_ack: bool,
+    pub cycle_mode: bool,
+    pub flag: bool,
+}
+impl Default for VrcIrq {
+    fn default() -> Self {
+        VrcIrq::new()
+    }
+}
+impl VrcIrq {
+    pub fn new() -> Self {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: 341,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            flag: false,
+        }
+    }
+    pub fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0b001 != 0;
+        self.enabled = data & 0b010 != 0;
+        self.cycle_mode = data & 0b100 != 0;
+        self.flag = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+    pub fn acknowledge(&mut self) {
+        self.flag = false;
+        self.enabled = self.enable_after_ack;
+    }
+    //called once per cpu cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.step();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += 341;
+                self.step();
+            }
+        }
+    }
+    fn step(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.flag = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+impl Snapshot for VrcIrq {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.u8(&mut self.latch);
+        s.u8(&mut self.counter);
+        s.i16_range(&mut self.prescaler, 1..=341);
+        s.bool(&mut self.enabled);
+        s.bool(&mut self.enable_after_ack);
+        s.bool(&mut self.cycle_mode);
+        s.bool(&mut self.flag);
+    }
+}
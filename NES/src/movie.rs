@@ -0,0 +1,307 @@
+use crate::savestate::StateError;
+use crate::Console;
+use std::fmt;
+/*
+Input movies: the buttons held on controller 1 for every frame, starting either
+from power-on or from a save state. Playing one back on the same rom reproduces
+the session exactly, the machine is deterministic given its inputs.
+
+File layout, all numbers little endian:
+  0   "NESM"   magic
+  4   u16      format version
+  6   u64      rom hash, as in save states (0 matches any rom)
+  14  u32      length of the start state, 0 when the movie starts at power-on
+  18  ...      start state
+      u32      frame count
+      ...      one byte of buttons per frame, A is bit 0 and Right is bit 7
+ */
+pub const MOVIE_MAGIC: [u8; 4] = *b"NESM";
+pub const MOVIE_VERSION: u16 = 1;
+#[derive(Debug, PartialEq, Clone)]
+pub enum MovieError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    WrongRom { expected: u64, found: u64 },
+    State(StateError),
+    //line number (from 1) and what is wrong with it
+    Fm2 { line: usize, message: String },
+}
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "Not a movie file"),
+            MovieError::UnsupportedVersion(version) => write!(
+                f,
+                "Movie version {} is not supported (expected {})",
+                version, MOVIE_VERSION
+            ),
+            MovieError::Truncated => write!(f, "Movie file ends early"),
+            MovieError::WrongRom { expected, found } => write!(
+                f,
+                "Movie is for rom {:016x}, this rom is {:016x}",
+                found, expected
+            ),
+            MovieError::State(e) => write!(f, "Movie start state: {}", e),
+            MovieError::Fm2 { line, message } => write!(f, "fm2 line {}: {}", line, message),
+        }
+    }
+}
+impl std::error::Error for MovieError {}
+pub struct Movie {
+    pub rom_hash: u64,
+    pub start_state: Option<Vec<u8>>,
+    pub inputs: Vec<u8>,
+}
+//reads little endian fields, running off the end is an error
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MovieError> {
+        let end = self.position.saturating_add(len);
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or(MovieError::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+    fn u16(&mut self) -> Result<u16, MovieError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+    fn u32(&mut self) -> Result<u32, MovieError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+    fn u64(&mut self) -> Result<u64, MovieError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+impl Movie {
+    //starts recording on the console as it is now, from_state false means it was just powered on
+    pub fn record(console: &mut Console, from_state: bool) -> Self {
+        Movie {
+            rom_hash: console.rom_hash,
+            start_state: if from_state {
+                Some(console.save_state())
+            } else {
+                None
+            },
+            inputs: Vec::new(),
+        }
+    }
+    //call after every recorded frame
+    pub fn record_frame(&mut self, console: &Console) {
+        self.inputs.push(console.cpu.bus.joypad1.button_status);
+    }
+    //puts a console in the movie's starting position, power-on movies need a fresh console
+    pub fn start(&self, console: &mut Console) -> Result<(), MovieError> {
+        if self.rom_hash != 0 && self.rom_hash != console.rom_hash {
+            return Err(MovieError::WrongRom {
+                expected: console.rom_hash,
+                found: self.rom_hash,
+            });
+        }
+        if let Some(state) = &self.start_state {
+            console.load_state(state).map_err(MovieError::State)?;
+        }
+        Ok(())
+    }
+    //sets the buttons for the given frame, false once the movie has run out
+    pub fn play_frame(&self, console: &mut Console, frame: usize) -> bool {
+        match self.inputs.get(frame) {
+            Some(buttons) => {
+                console.set_buttons(*buttons);
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let state: &[u8] = self.start_state.as_deref().unwrap_or(&[]);
+        let mut data = Vec::with_capacity(22 + state.len() + self.inputs.len());
+        data.extend_from_slice(&MOVIE_MAGIC);
+        data.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        data.extend_from_slice(&self.rom_hash.to_le_bytes());
+        data.extend_from_slice(&(state.len() as u32).to_le_bytes());
+        data.extend_from_slice(state);
+        data.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.inputs);
+        data
+    }
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = Reader {
+            data: data,
+            position: 0,
+        };
+        if reader.take(4)? != MOVIE_MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = reader.u64()?;
+        let state_len = reader.u32()? as usize;
+        let start_state = match state_len {
+            0 => None,
+            len => Some(reader.take(len)?.to_vec()),
+        };
+        let frames = reader.u32()? as usize;
+        let inputs = reader.take(frames)?.to_vec();
+        Ok(Movie {
+            rom_hash: rom_hash,
+            start_state: start_state,
+            inputs: inputs,
+        })
+    }
+    /*
+    Imports an FCEUX text movie. Only controller 1 is used. fm2 identifies roms by
+    md5 which is not checked here, the movie gets rom hash 0. Movies that start from
+    an FCEUX save state, binary movies and resets after the first frame can not be
+    played and are rejected.
+    Input lines look like |c|RLDUTSBA|........||, c being the command bits
+    (1: soft reset, 2: power) and any character but '.' or ' ' a held button.
+     */
+    pub fn from_fm2(text: &str) -> Result<Self, MovieError> {
+        let mut inputs = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| MovieError::Fm2 {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let line = line.trim_end();
+            if !line.starts_with('|') {
+                let mut parts = line.splitn(2, ' ');
+                let key = parts.next().unwrap_or("");
+                let value = parts.next().unwrap_or("").trim();
+                match key {
+                    "binary" if value != "0" && value != "false" => {
+                        return Err(error("binary movies are not supported"))
+                    }
+                    "savestate" if !value.is_empty() => {
+                        return Err(error("movies starting from a save state are not supported"))
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < 3 {
+                return Err(error("expected |commands|port0|"));
+            }
+            let commands: u8 = fields[1]
+                .trim()
+                .parse()
+                .map_err(|_| error("bad command field"))?;
+            //the first frame is always power-on here
+            if commands & 0b11 != 0 && !inputs.is_empty() {
+                return Err(error("resets are not supported"));
+            }
+            let mut buttons = 0;
+            for (bit, key) in fields[2].chars().take(8).enumerate() {
+                if key != '.' && key != ' ' {
+                    buttons |= 1 << (7 - bit);
+                }
+            }
+            inputs.push(buttons);
+        }
+        Ok(Movie {
+            rom_hash: 0,
+            start_state: None,
+            inputs: inputs,
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn fm2_buttons_map_right_to_bit_7_and_a_to_bit_0() {
+        let text = "version 3\n\
+                    |0|R.......|||\n\
+                    |0|.L......|||\n\
+                    |0|..D.....|||\n\
+                    |0|...U....|||\n\
+                    |0|....T...|||\n\
+                    |0|.....S..|||\n\
+                    |0|......B.|||\n\
+                    |0|.......A|||\n\
+                    |0|RLDUTSBA|||\n\
+                    |0|        |||\n";
+        let movie = Movie::from_fm2(text).unwrap();
+        assert_eq!(
+            movie.inputs,
+            vec![0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01, 0xff, 0x00]
+        );
+        assert_eq!(movie.rom_hash, 0);
+        assert!(movie.start_state.is_none());
+    }
+    #[test]
+    fn fm2_rejects_binary_movies_and_save_states() {
+        assert_eq!(
+            Movie::from_fm2("version 3\nbinary 1\n").err(),
+            Some(MovieError::Fm2 {
+                line: 2,
+                message: String::from("binary movies are not supported"),
+            })
+        );
+        assert!(Movie::from_fm2("binary 0\n|0|........|||\n").is_ok());
+        assert_eq!(
+            Movie::from_fm2("savestate base64:AAAA\n").err(),
+            Some(MovieError::Fm2 {
+                line: 1,
+                message: String::from("movies starting from a save state are not supported"),
+            })
+        );
+    }
+    #[test]
+    fn fm2_allows_a_reset_only_on_the_first_frame() {
+        assert!(Movie::from_fm2("|1|........|||\n|0|........|||\n").is_ok());
+        for command in &["1", "2", "3"] {
+            let text = format!("|0|........|||\n|{}|........|||\n", command);
+            assert_eq!(
+                Movie::from_fm2(&text).err(),
+                Some(MovieError::Fm2 {
+                    line: 2,
+                    message: String::from("resets are not supported"),
+                })
+            );
+        }
+    }
+    #[test]
+    fn binary_round_trip_and_truncation() {
+        let movie = Movie {
+            rom_hash: 0x0123_4567_89ab_cdef,
+            start_state: Some(vec![1, 2, 3]),
+            inputs: vec![0x08, 0x00, 0x81],
+        };
+        let data = movie.to_bytes();
+        let read = Movie::from_bytes(&data).unwrap();
+        assert_eq!(read.rom_hash, movie.rom_hash);
+        assert_eq!(read.start_state, movie.start_state);
+        assert_eq!(read.inputs, movie.inputs);
+        //cut anywhere, including inside the start state and the inputs
+        for len in 0..data.len() {
+            assert_eq!(
+                Movie::from_bytes(&data[..len]).err(),
+                Some(MovieError::Truncated)
+            );
+        }
+        let mut bad = data.clone();
+        bad[0] = b'X';
+        assert_eq!(Movie::from_bytes(&bad).err(), Some(MovieError::BadMagic));
+        let mut bad = data;
+        bad[4] = 2;
+        assert_eq!(
+            Movie::from_bytes(&bad).err(),
+            Some(MovieError::UnsupportedVersion(2))
+        );
+    }
+}
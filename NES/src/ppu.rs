@@ -1,68 +1,127 @@
-use crate::ppu_utils::AddressRegister::AddressRegister;
+use crate::mapper::SharedMapper;
 use crate::ppu_utils::ControllerRegister::ControllerRegister;
+use crate::ppu_utils::LoopyRegister::LoopyRegister;
+use crate::ppu_utils::MaskRegister::MaskFlag;
 use crate::ppu_utils::MaskRegister::MaskRegister;
-use crate::ppu_utils::ScrollRegister::ScrollRegister;
 use crate::ppu_utils::StatusRegister::StatusFlag;
 use crate::ppu_utils::StatusRegister::StatusRegister;
+use crate::render::frame::Frame;
+use crate::render::palette;
 use crate::rom::Mirroring;
+use crate::savestate::{Snapshot, StateStream};
 pub struct PPU {
-    pub chr_rom: Vec<u8>,
+    pub mapper: SharedMapper,
     pub palette: [u8; 32],
     pub vram: [u8; 2048],
-    pub mirroring: Mirroring,
     pub oam_address: u8,
     pub oam_data: [u8; 256],
     pub controller: ControllerRegister,
-    pub scroll: ScrollRegister,
-    pub address: AddressRegister,
+    pub loopy: LoopyRegister,
     pub mask: MaskRegister,
     pub status: StatusRegister,
     pub data_buffer: u8,
     pub scanline: u16,
     pub cycles: usize,
+    //dots since power on, time stamps for the cartridge's view of the address bus
+    pub dots: u64,
     pub nmi_interrupt: Option<u8>,
+    pub frame: Frame,
+    pub odd_frame: bool,
+    //background pipeline
+    pub nametable_latch: u8,
+    pub attribute_latch: u8,
+    pub pattern_lo_latch: u8,
+    pub pattern_hi_latch: u8,
+    pub bg_shift_lo: u16,
+    pub bg_shift_hi: u16,
+    pub attribute_shift_lo: u16,
+    pub attribute_shift_hi: u16,
+    //sprites evaluated for the scanline being drawn
+    pub sprite_count: usize,
+    pub sprite_zero_on_line: bool,
+    pub sprite_x: [u8; 8],
+    pub sprite_attributes: [u8; 8],
+    pub sprite_pattern_lo: [u8; 8],
+    pub sprite_pattern_hi: [u8; 8],
+    pub sprite_fetch_address: [u16; 8],
 }
 impl PPU {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+    pub fn new(mapper: SharedMapper) -> Self {
         PPU {
-            chr_rom: chr_rom,
+            mapper: mapper,
             palette: [0; 32],
             vram: [0; 2048],
-            mirroring: mirroring,
             oam_address: 0,
             oam_data: [0; 64 * 4],
             controller: ControllerRegister::new(),
-            scroll: ScrollRegister::new(),
-            address: AddressRegister::new(),
+            loopy: LoopyRegister::new(),
             mask: MaskRegister::new(),
             status: StatusRegister::new(),
             data_buffer: 0,
             scanline: 0,
             cycles: 0,
+            dots: 0,
             nmi_interrupt: None,
+            frame: Frame::new(),
+            odd_frame: false,
+            nametable_latch: 0,
+            attribute_latch: 0,
+            pattern_lo_latch: 0,
+            pattern_hi_latch: 0,
+            bg_shift_lo: 0,
+            bg_shift_hi: 0,
+            attribute_shift_lo: 0,
+            attribute_shift_hi: 0,
+            sprite_count: 0,
+            sprite_zero_on_line: false,
+            sprite_x: [0; 8],
+            sprite_attributes: [0; 8],
+            sprite_pattern_lo: [0; 8],
+            sprite_pattern_hi: [0; 8],
+            sprite_fetch_address: [0; 8],
         }
     }
+    //advances the ppu one dot at a time, returns true when a frame has been completed
     pub fn tick(&mut self, cycles: u8) -> bool {
+        let mut frame_done = false;
+        for _ in 0..cycles {
+            if self.step_dot() {
+                frame_done = true;
+            }
+        }
+        frame_done
+    }
+    fn step_dot(&mut self) -> bool {
         /*println!(
             "ppu cycle: {}. ppu scanline: {}\n",
             self.cycles, self.scanline
         );*/
-        self.cycles += cycles as usize;
+        if self.scanline == 261 && self.cycles == 1 {
+            self.status.disable_flag(&StatusFlag::VBlank);
+            self.status.disable_flag(&StatusFlag::SpriteZero);
+            self.status.disable_flag(&StatusFlag::SpriteOverflow);
+        }
+        self.render_dot();
+        self.cycles += 1;
+        self.dots += 1;
+        //odd frames skip the last dot of the pre-render scanline while rendering
+        if self.scanline == 261 && self.cycles == 340 && self.odd_frame && self.rendering_enabled()
+        {
+            self.cycles = 341;
+        }
         if self.cycles >= 341 {
             self.cycles = self.cycles - 341;
             self.scanline += 1;
             if self.scanline == 241 {
                 self.status.enable_flag(&StatusFlag::VBlank);
-                self.status.disable_flag(&StatusFlag::SpriteZero);
                 if self.controller.generate_nmi() {
                     self.nmi_interrupt = Some(1);
                 }
             }
             if self.scanline >= 262 {
                 self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
                 self.nmi_interrupt = None;
-                self.status.disable_flag(&StatusFlag::SpriteZero);
-                self.status.disable_flag(&StatusFlag::VBlank);
                 return true;
             }
         }
@@ -76,14 +135,15 @@ impl PPU {
         self.mask.update(data);
     }
     pub fn write_ppu_address(&mut self, data: u8) {
-        self.address.update(data);
+        self.loopy.write_address(data);
     }
     pub fn write_scroll(&mut self, data: u8) {
-        self.scroll.write(data);
+        self.loopy.write_scroll(data);
     }
     pub fn write_controller(&mut self, data: u8) {
         let nmi_copy = self.controller.generate_nmi();
         self.controller.update(data);
+        self.loopy.write_controller(data);
         if !nmi_copy
             && self.controller.generate_nmi()
             && self.status.get_register_status(&StatusFlag::VBlank)
@@ -95,12 +155,17 @@ impl PPU {
         let data = self.status.snapshot();
         //println!("read status data: {:x}", data);
         self.status.disable_flag(&StatusFlag::VBlank);
-        self.address.reset_latch();
-        self.scroll.reset_latch();
+        self.loopy.reset_latch();
         data
     }
     fn increment_vram_address(&mut self) {
-        self.address.increment(self.controller.vram_address_inc());
+        //while rendering, $2007 accesses bump v through the renderer's own increments
+        if self.rendering_enabled() && (self.scanline < 240 || self.scanline == 261) {
+            self.loopy.increment_coarse_x();
+            self.loopy.increment_y();
+            return;
+        }
+        self.loopy.increment(self.controller.vram_address_inc());
     }
     pub fn write_oam_address(&mut self, data: u8) {
         self.oam_address = data;
@@ -113,31 +178,46 @@ impl PPU {
         self.oam_data[self.oam_address as usize]
     }
     pub fn read_data(&mut self) -> u8 {
-        let addr = self.address.get();
+        let addr = self.loopy.get();
         self.increment_vram_address();
         match addr {
             0..=0x1fff => {
                 let result = self.data_buffer;
-                self.data_buffer = self.chr_rom[addr as usize];
+                self.data_buffer = self.fetch_chr(addr);
                 result
             }
-            0x2000..=0x2fff => {
-                //mirrored location
+            0x2000..=0x3eff => {
+                //$3000-$3EFF mirrors the nametables
                 let result = self.data_buffer;
-                self.data_buffer = self.vram[self.mirror_address(addr) as usize];
+                self.data_buffer = self.fetch_nametable_byte(addr & 0x2fff);
                 result
             }
-            0x3000..=0x3eff => panic!(
-                "Address space 0x3000..0x3eff is not used by PPU, requested: {}",
-                addr
-            ),
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                //various mirrored locations
-                let mirror = addr - 0x10;
-                self.palette[(mirror - 0x3f00) as usize]
+            _ => {
+                //palette reads skip the buffer, which picks up the nametable byte underneath
+                self.data_buffer = self.fetch_nametable_byte(addr & 0x2fff);
+                self.palette[palette_index(addr)]
             }
-            0x3f00..=0x3fff => self.palette[(addr - 0x3f00) as usize],
-            _ => panic!("Unexpected read of mirrored space at: {}", addr),
+        }
+    }
+    //pattern table access, banked by the cartridge
+    pub fn read_chr(&self, addr: u16) -> u8 {
+        self.mapper.borrow().read_chr(addr)
+    }
+    //a read the cartridge sees on the bus, as opposed to debug peeks through read_chr
+    fn fetch_chr(&mut self, addr: u16) -> u8 {
+        let mut mapper = self.mapper.borrow_mut();
+        mapper.ppu_address(addr, self.dots);
+        mapper.read_chr(addr)
+    }
+    pub fn read_sprite_chr(&self, addr: u16) -> u8 {
+        self.mapper.borrow().read_sprite_chr(addr)
+    }
+    //nametable and attribute reads, the cartridge gets the first look
+    fn fetch_nametable_byte(&mut self, addr: u16) -> u8 {
+        let data = self.mapper.borrow_mut().read_nametable(addr);
+        match data {
+            Some(data) => data,
+            None => self.vram[self.mirror_address(addr) as usize],
         }
     }
     pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
@@ -147,26 +227,21 @@ impl PPU {
         }
     }
     pub fn write_data(&mut self, data: u8) {
-        let addr = self.address.get();
+        let addr = self.loopy.get();
         match addr {
             0..=0x1fff => {
-                panic!("Attempt to write to chr rom space at: {}", addr);
+                let mut mapper = self.mapper.borrow_mut();
+                mapper.ppu_address(addr, self.dots);
+                mapper.write_chr(addr, data);
             }
-            0x2000..=0x2fff => {
-                //mirrored location
-                self.vram[self.mirror_address(addr) as usize] = data;
-            }
-            0x3000..=0x3eff => panic!(
-                "Address space 0x3000..0x3eff is not used by PPU, requested: {}",
-                addr
-            ),
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                //various mirrored locations
-                let mirror = addr - 0x10;
-                self.palette[(mirror - 0x3f00) as usize] = data;
+            0x2000..=0x3eff => {
+                //mirrored location, unless the cartridge maps its own memory there
+                let addr = addr & 0x2fff;
+                if !self.mapper.borrow_mut().write_nametable(addr, data) {
+                    self.vram[self.mirror_address(addr) as usize] = data;
+                }
             }
-            0x3f00..=0x3fff => self.palette[(addr - 0x3f00) as usize] = data,
-            _ => panic!("Unexpected write to mirrored space at: {}", addr),
+            _ => self.palette[palette_index(addr)] = data,
         }
         self.increment_vram_address();
     }
@@ -177,16 +252,365 @@ impl PPU {
     // Vertical:
     //   [ A ] [ B ]
     //   [ a ] [ b ]
+
+    // Single screen:
+    //   [ A ] [ a ]
+    //   [ a ] [ a ]
+
+    // Four screen:
+    //   [ A ] [ B ]
+    //   [ C ] [ D ]   C and D are ram on the cartridge
+
+    // Custom: the cartridge picks the vram page of each nametable
     pub fn mirror_address(&self, addr: u16) -> u16 {
         let mirrored_vram = addr & 0b10111111111111; //shift 0x3000-3eff to 0x2000-0x2eff
         let vram_index = mirrored_vram - 0x2000;
         let name_table = vram_index / 0x400;
-        match (&self.mirroring, name_table) {
+        match (self.mapper.borrow().mirroring(), name_table) {
             (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_index - 0x800,
             (Mirroring::Horizontal, 1) => vram_index - 0x400,
             (Mirroring::Horizontal, 2) => vram_index - 0x400,
-            (Mirroring::Horizontal, 3) => vram_index - 0x400,
+            (Mirroring::Horizontal, 3) => vram_index - 0x800,
+            (Mirroring::SingleScreenLower, _) => vram_index % 0x400,
+            (Mirroring::SingleScreenUpper, _) => vram_index % 0x400 + 0x400,
+            (Mirroring::Custom(pages), n) => pages[n as usize] as u16 * 0x400 + vram_index % 0x400,
+            //nametables 2 and 3 live on four screen cartridges, which answer for them first
+            (Mirroring::Fourscreen, _) => vram_index % 0x800,
             _ => vram_index,
         }
     }
+    /*
+     * * * * * * * * * * Dot renderer starts here * * * * * * * * * *
+     */
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask.get_register_status(&MaskFlag::Background)
+            || self.mask.get_register_status(&MaskFlag::Sprite)
+    }
+    fn render_dot(&mut self) {
+        let dot = self.cycles;
+        let visible = self.scanline < 240;
+        let prerender = self.scanline == 261;
+        if !self.rendering_enabled() {
+            if visible && dot >= 1 && dot <= 256 {
+                let rgb = palette::masked_color(&self.mask, self.palette[0]);
+                self.frame.set_pixel(dot - 1, self.scanline as usize, rgb);
+            }
+            return;
+        }
+        if !visible && !prerender {
+            return;
+        }
+        if (dot >= 2 && dot <= 257) || (dot >= 322 && dot <= 337) {
+            self.shift_background();
+        }
+        if (dot >= 1 && dot <= 256) || (dot >= 321 && dot <= 336) {
+            match (dot - 1) % 8 {
+                0 => {
+                    if dot >= 9 {
+                        self.load_background();
+                    }
+                    self.fetch_nametable();
+                }
+                2 => self.fetch_attribute(),
+                4 => self.fetch_pattern(0),
+                6 => self.fetch_pattern(8),
+                7 => self.loopy.increment_coarse_x(),
+                _ => {}
+            }
+        }
+        match dot {
+            256 => self.loopy.increment_y(),
+            257 => {
+                self.load_background();
+                self.loopy.copy_horizontal();
+                if visible {
+                    self.evaluate_sprites();
+                } else {
+                    self.sprite_count = 0;
+                    self.sprite_fetch_address = [self.sprite_row_address(0xff, 0); 8];
+                }
+            }
+            280..=304 if prerender => self.loopy.copy_vertical(),
+            337 => {
+                self.load_background();
+                //two unused nametable reads end the line, MMC5 counts scanlines off them
+                self.fetch_nametable();
+            }
+            339 => self.fetch_nametable(),
+            _ => {}
+        }
+        //sprite patterns are already latched, the cartridge sees the reads over dots 257-320
+        if dot >= 257 && dot <= 320 {
+            let addr = self.sprite_fetch_address[(dot - 257) / 8];
+            match (dot - 257) % 8 {
+                4 => self.mapper.borrow_mut().ppu_address(addr, self.dots),
+                6 => self.mapper.borrow_mut().ppu_address(addr + 8, self.dots),
+                _ => {}
+            }
+        }
+        if visible && dot >= 1 && dot <= 256 {
+            self.output_pixel(dot - 1);
+        }
+    }
+    fn fetch_nametable(&mut self) {
+        let addr = 0x2000 | (self.loopy.v & 0x0fff);
+        self.nametable_latch = self.fetch_nametable_byte(addr);
+    }
+    fn fetch_attribute(&mut self) {
+        let v = self.loopy.v;
+        let addr = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        let atbyte = self.fetch_nametable_byte(addr);
+        //pick the 2x2 tile quadrant from bit 1 of coarse x and coarse y
+        let shift = ((v >> 4) & 0b100) | (v & 0b10);
+        self.attribute_latch = (atbyte >> shift) & 0b11;
+    }
+    fn fetch_pattern(&mut self, plane: u16) {
+        let fine_y = (self.loopy.v >> 12) & 0b111;
+        let addr = self.controller.background_address()
+            + self.nametable_latch as u16 * 16
+            + fine_y
+            + plane;
+        let data = self.fetch_chr(addr);
+        if plane == 0 {
+            self.pattern_lo_latch = data;
+        } else {
+            self.pattern_hi_latch = data;
+        }
+    }
+    fn load_background(&mut self) {
+        self.bg_shift_lo = (self.bg_shift_lo & 0xff00) | self.pattern_lo_latch as u16;
+        self.bg_shift_hi = (self.bg_shift_hi & 0xff00) | self.pattern_hi_latch as u16;
+        let lo = if self.attribute_latch & 0b01 != 0 {
+            0xff
+        } else {
+            0
+        };
+        let hi = if self.attribute_latch & 0b10 != 0 {
+            0xff
+        } else {
+            0
+        };
+        self.attribute_shift_lo = (self.attribute_shift_lo & 0xff00) | lo;
+        self.attribute_shift_hi = (self.attribute_shift_hi & 0xff00) | hi;
+    }
+    fn shift_background(&mut self) {
+        self.bg_shift_lo = self.bg_shift_lo << 1;
+        self.bg_shift_hi = self.bg_shift_hi << 1;
+        self.attribute_shift_lo = self.attribute_shift_lo << 1;
+        self.attribute_shift_hi = self.attribute_shift_hi << 1;
+    }
+    //pattern address of one row of a sprite. in 8x16 mode bit 0 of the tile index picks
+    //the pattern table and rows 8-15 come from the following tile
+    pub fn sprite_row_address(&self, tile: u8, row: u16) -> u16 {
+        if self.controller.sprite_size() == 16 {
+            let bank = (tile as u16 & 1) * 0x1000;
+            let tile = (tile & 0xfe) as u16 + row / 8;
+            bank + tile * 16 + row % 8
+        } else {
+            self.controller.sprite_address() + tile as u16 * 16 + row
+        }
+    }
+    //finds the first 8 sprites that land on the next scanline and fetches their patterns
+    fn evaluate_sprites(&mut self) {
+        let height = self.controller.sprite_size() as i32;
+        self.sprite_count = 0;
+        self.sprite_zero_on_line = false;
+        //empty slots still fetch tile $ff
+        self.sprite_fetch_address = [self.sprite_row_address(0xff, 0); 8];
+        let mut n = 0;
+        while n < 64 && self.sprite_count < 8 {
+            let i = n * 4;
+            let row = self.scanline as i32 - self.oam_data[i] as i32;
+            n += 1;
+            if row < 0 || row >= height {
+                continue;
+            }
+            let tile = self.oam_data[i + 1];
+            let attributes = self.oam_data[i + 2];
+            let row = if attributes >> 7 & 1 == 1 {
+                (height - 1 - row) as u16
+            } else {
+                row as u16
+            };
+            let addr = self.sprite_row_address(tile, row);
+            let mut lo = self.read_sprite_chr(addr);
+            let mut hi = self.read_sprite_chr(addr + 8);
+            if attributes >> 6 & 1 == 1 {
+                lo = lo.reverse_bits();
+                hi = hi.reverse_bits();
+            }
+            if i == 0 {
+                self.sprite_zero_on_line = true;
+            }
+            let slot = self.sprite_count;
+            self.sprite_fetch_address[slot] = addr;
+            self.sprite_x[slot] = self.oam_data[i + 3];
+            self.sprite_attributes[slot] = attributes;
+            self.sprite_pattern_lo[slot] = lo;
+            self.sprite_pattern_hi[slot] = hi;
+            self.sprite_count += 1;
+        }
+        //once 8 sprites are found the hardware keeps looking for a ninth, but it also bumps
+        //the byte offset on every miss so tile, attribute and x bytes get read as y coordinates
+        let mut m = 0;
+        while n < 64 {
+            let row = self.scanline as i32 - self.oam_data[n * 4 + m] as i32;
+            if row >= 0 && row < height {
+                self.status.enable_flag(&StatusFlag::SpriteOverflow);
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+    }
+    fn output_pixel(&mut self, x: usize) {
+        let show_background = self.mask.get_register_status(&MaskFlag::Background)
+            && (x >= 8 || self.mask.get_register_status(&MaskFlag::LeftBackground));
+        let show_sprites = self.mask.get_register_status(&MaskFlag::Sprite)
+            && (x >= 8 || self.mask.get_register_status(&MaskFlag::LeftSprite));
+        let bit = 15 - self.loopy.x as u16;
+        let mut bg_pixel = ((self.bg_shift_hi >> bit) & 1) << 1 | ((self.bg_shift_lo >> bit) & 1);
+        if !show_background {
+            bg_pixel = 0;
+        }
+        let bg_palette =
+            ((self.attribute_shift_hi >> bit) & 1) << 1 | ((self.attribute_shift_lo >> bit) & 1);
+        let mut sprite_pixel = 0;
+        let mut sprite_palette = 0;
+        let mut sprite_behind = false;
+        let sprite_count = if show_sprites { self.sprite_count } else { 0 };
+        for n in 0..sprite_count {
+            let offset = x as i32 - self.sprite_x[n] as i32;
+            if offset < 0 || offset > 7 {
+                continue;
+            }
+            let bit = 7 - offset;
+            let value = ((self.sprite_pattern_hi[n] >> bit) & 1) << 1
+                | ((self.sprite_pattern_lo[n] >> bit) & 1);
+            if value != 0 {
+                if n == 0 && self.sprite_zero_on_line && bg_pixel != 0 && x != 255 {
+                    self.status.enable_flag(&StatusFlag::SpriteZero);
+                }
+                sprite_pixel = value;
+                sprite_palette = self.sprite_attributes[n] & 0b11;
+                sprite_behind = self.sprite_attributes[n] >> 5 & 1 == 1;
+                break;
+            }
+        }
+        //the first opaque sprite decides priority, even when it loses to the background
+        let palidx = if sprite_pixel != 0 && (bg_pixel == 0 || !sprite_behind) {
+            0x10 + (sprite_palette * 4 + sprite_pixel) as usize
+        } else if bg_pixel != 0 {
+            (bg_palette * 4 + bg_pixel) as usize
+        } else {
+            0
+        };
+        let rgb = palette::masked_color(&self.mask, self.palette[palidx]);
+        self.frame.set_pixel(x, self.scanline as usize, rgb);
+    }
+}
+//the frame buffer is left out, every visible dot is redrawn each frame
+impl Snapshot for PPU {
+    fn snapshot(&mut self, s: &mut StateStream) {
+        s.bytes(&mut self.palette);
+        s.bytes(&mut self.vram);
+        s.u8(&mut self.oam_address);
+        s.bytes(&mut self.oam_data);
+        s.u8(&mut self.controller.cregister);
+        s.u16(&mut self.loopy.v);
+        s.u16(&mut self.loopy.t);
+        s.u8_below(&mut self.loopy.x, 8);
+        s.bool(&mut self.loopy.w);
+        s.u8(&mut self.mask.mregister);
+        s.u8(&mut self.status.sregister);
+        s.u8(&mut self.data_buffer);
+        s.u16_range(&mut self.scanline, 0..=261);
+        s.usize_below(&mut self.cycles, 341);
+        s.u64(&mut self.dots);
+        s.option_u8(&mut self.nmi_interrupt);
+        s.bool(&mut self.odd_frame);
+        s.u8(&mut self.nametable_latch);
+        s.u8(&mut self.attribute_latch);
+        s.u8(&mut self.pattern_lo_latch);
+        s.u8(&mut self.pattern_hi_latch);
+        s.u16(&mut self.bg_shift_lo);
+        s.u16(&mut self.bg_shift_hi);
+        s.u16(&mut self.attribute_shift_lo);
+        s.u16(&mut self.attribute_shift_hi);
+        s.usize_below(&mut self.sprite_count, 9);
+        s.bool(&mut self.sprite_zero_on_line);
+        s.bytes(&mut self.sprite_x);
+        s.bytes(&mut self.sprite_attributes);
+        s.bytes(&mut self.sprite_pattern_lo);
+        s.bytes(&mut self.sprite_pattern_hi);
+        for value in self.sprite_fetch_address.iter_mut() {
+            s.u16(value);
+        }
+    }
+}
+//the 32 palette entries repeat up to $3FFF, and the sprite backdrops at $3F10/$14/$18/$1C
+//are the background ones
+fn palette_index(addr: u16) -> usize {
+    let index = addr as usize & 0x1f;
+    if index & 0x13 == 0x10 {
+        index & 0x0f
+    } else {
+        index
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Rom;
+    //16K of prg and 8K of chr-ram on the given mapper, flags 6 bit 3 asks for four screen
+    fn ppu(mapper: u8, four_screen: bool) -> PPU {
+        let flags6 = mapper << 4 | if four_screen { 0b1000 } else { 0 };
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, flags6, mapper & 0xf0];
+        raw.resize(16 + 0x4000, 0);
+        PPU::new(crate::mapper::new(Rom::new(&raw).unwrap()).unwrap())
+    }
+    fn write(ppu: &mut PPU, addr: u16, data: u8) {
+        ppu.write_ppu_address((addr >> 8) as u8);
+        ppu.write_ppu_address(addr as u8);
+        ppu.write_data(data);
+    }
+    //reads through the buffer, so below the palette the first read is thrown away
+    fn read(ppu: &mut PPU, addr: u16) -> u8 {
+        ppu.write_ppu_address((addr >> 8) as u8);
+        ppu.write_ppu_address(addr as u8);
+        if addr < 0x3f00 {
+            ppu.read_data();
+        }
+        ppu.read_data()
+    }
+    #[test]
+    fn four_screen_nametables_are_separate() {
+        let mut ppu = ppu(4, true);
+        for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2c00].iter().enumerate() {
+            write(&mut ppu, *addr, i as u8 + 1);
+        }
+        for (i, addr) in [0x2000, 0x2400, 0x2800, 0x2c00].iter().enumerate() {
+            assert_eq!(read(&mut ppu, *addr), i as u8 + 1);
+        }
+    }
+    #[test]
+    fn nametables_mirror_up_to_the_palette() {
+        let mut ppu = ppu(0, false);
+        write(&mut ppu, 0x3123, 0x42);
+        assert_eq!(read(&mut ppu, 0x2123), 0x42);
+        write(&mut ppu, 0x2456, 0x24);
+        assert_eq!(read(&mut ppu, 0x3456), 0x24);
+    }
+    #[test]
+    fn palette_mirrors_and_backdrop_aliases() {
+        let mut ppu = ppu(0, false);
+        write(&mut ppu, 0x3f10, 0x11);
+        assert_eq!(read(&mut ppu, 0x3f00), 0x11);
+        write(&mut ppu, 0x3f0c, 0x22);
+        assert_eq!(read(&mut ppu, 0x3f1c), 0x22);
+        write(&mut ppu, 0x3fe5, 0x33);
+        assert_eq!(read(&mut ppu, 0x3f05), 0x33);
+        write(&mut ppu, 0x3fff, 0x2a);
+        assert_eq!(read(&mut ppu, 0x3f1f), 0x2a);
+    }
 }
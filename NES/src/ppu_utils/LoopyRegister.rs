@@ -0,0 +1,94 @@
+/*
+Internal ppu registers shared by $2000, $2005, $2006 and the renderer.
+v and t are laid out as:
+yyy NN YYYYY XXXXX
+||| || ||||| +++++- coarse X scroll
+||| || +++++------- coarse Y scroll
+||| ++------------- nametable select
++++---------------- fine Y scroll
+v is the current vram address, t the temporary address the cpu writes into,
+x the fine X scroll and w the shared first/second write toggle.
+ */
+pub struct LoopyRegister {
+    pub v: u16,
+    pub t: u16,
+    pub x: u8,
+    pub w: bool,
+}
+impl Default for LoopyRegister {
+    fn default() -> Self {
+        LoopyRegister::new()
+    }
+}
+impl LoopyRegister {
+    pub fn new() -> Self {
+        LoopyRegister {
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+        }
+    }
+    pub fn write_controller(&mut self, data: u8) {
+        self.t = (self.t & !0x0c00) | ((data as u16 & 0b11) << 10);
+    }
+    pub fn write_scroll(&mut self, data: u8) {
+        if !self.w {
+            self.t = (self.t & !0x001f) | (data as u16 >> 3);
+            self.x = data & 0b111;
+        } else {
+            self.t = (self.t & !0x73e0) | ((data as u16 & 0b111) << 12) | ((data as u16 >> 3) << 5);
+        }
+        self.w = !self.w;
+    }
+    pub fn write_address(&mut self, data: u8) {
+        if !self.w {
+            //bit 14 of t is cleared by the first write
+            self.t = (self.t & 0x00ff) | ((data as u16 & 0x3f) << 8);
+        } else {
+            self.t = (self.t & 0xff00) | data as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+    pub fn reset_latch(&mut self) {
+        self.w = false;
+    }
+    pub fn increment(&mut self, inc: u8) {
+        self.v = self.v.wrapping_add(inc as u16) & 0x7fff;
+    }
+    pub fn get(&self) -> u16 {
+        self.v & 0x3fff
+    }
+    pub fn increment_coarse_x(&mut self) {
+        if self.v & 0x001f == 31 {
+            self.v = self.v & !0x001f;
+            self.v = self.v ^ 0x0400; //switch horizontal nametable
+        } else {
+            self.v += 1;
+        }
+    }
+    pub fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+        } else {
+            self.v = self.v & !0x7000;
+            let mut coarse_y = (self.v & 0x03e0) >> 5;
+            if coarse_y == 29 {
+                coarse_y = 0;
+                self.v = self.v ^ 0x0800; //switch vertical nametable
+            } else if coarse_y == 31 {
+                coarse_y = 0; //attribute rows wrap without switching nametables
+            } else {
+                coarse_y += 1;
+            }
+            self.v = (self.v & !0x03e0) | (coarse_y << 5);
+        }
+    }
+    pub fn copy_horizontal(&mut self) {
+        self.v = (self.v & !0x041f) | (self.t & 0x041f);
+    }
+    pub fn copy_vertical(&mut self) {
+        self.v = (self.v & !0x7be0) | (self.t & 0x7be0);
+    }
+}
@@ -1,5 +1,4 @@
-pub mod AddressRegister;
 pub mod ControllerRegister;
+pub mod LoopyRegister;
 pub mod MaskRegister;
-pub mod ScrollRegister;
 pub mod StatusRegister;
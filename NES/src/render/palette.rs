@@ -1,3 +1,6 @@
+use crate::ppu_utils::MaskRegister::Color;
+use crate::ppu_utils::MaskRegister::MaskFlag;
+use crate::ppu_utils::MaskRegister::MaskRegister;
 #[rustfmt::skip]
 pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
@@ -14,3 +17,33 @@ pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
    (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
 ];
+//system palette lookup with the greyscale and color emphasis bits of $2001 applied
+pub fn masked_color(mask: &MaskRegister, index: u8) -> (u8, u8, u8) {
+    let index = if mask.get_register_status(&MaskFlag::Greyscale) {
+        index & 0x30
+    } else {
+        index & 0x3f
+    };
+    let (mut r, mut g, mut b) = SYSTEM_PALLETE[index as usize];
+    //each emphasis bit darkens the two channels it does not emphasize
+    for color in mask.emphasise() {
+        match color {
+            Color::Red => {
+                g = attenuate(g);
+                b = attenuate(b);
+            }
+            Color::Green => {
+                r = attenuate(r);
+                b = attenuate(b);
+            }
+            Color::Blue => {
+                r = attenuate(r);
+                g = attenuate(g);
+            }
+        }
+    }
+    (r, g, b)
+}
+fn attenuate(channel: u8) -> u8 {
+    (channel as u32 * 816 / 1000) as u8
+}
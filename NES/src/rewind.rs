@@ -0,0 +1,312 @@
+use crate::Console;
+use std::collections::VecDeque;
+/*
+Rewind history built from save states. A state is taken every `interval` frames,
+the first state of each group is a keyframe and the rest are stored as deltas
+against it. Both are run length encoded after xoring with their base (the keyframe
+for deltas, zeros for keyframes):
+  varint   length of the state
+  repeated
+    varint   bytes equal to the base
+    varint   count of bytes that differ
+    ...      those bytes xored with the base
+The controller state of every frame since the oldest state is kept as well, so
+stepping back restores the closest earlier state and replays the frames after it.
+When the history grows past the memory budget the oldest group is dropped whole,
+its deltas are useless without the keyframe.
+ */
+pub struct RewindPoint {
+    //frames run since the history started
+    pub frame: u64,
+    pub data: Vec<u8>,
+}
+pub struct RewindGroup {
+    pub keyframe: RewindPoint,
+    pub deltas: Vec<RewindPoint>,
+}
+pub struct Rewind {
+    pub interval: u64,
+    pub group_size: usize,
+    pub budget: usize,
+    pub groups: VecDeque<RewindGroup>,
+    //unpacked keyframe of the newest group, the base for new deltas
+    pub keyframe: Vec<u8>,
+    //buttons held during each frame, starting at inputs_start
+    pub inputs: VecDeque<u8>,
+    pub inputs_start: u64,
+    pub frame: u64,
+    pub used: usize,
+}
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+fn read_varint(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(byte) = data.get(*position) {
+        *position += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+//bytes past the end of the base count as zero
+pub fn encode(base: &[u8], state: &[u8]) -> Vec<u8> {
+    let base_at = |i: usize| base.get(i).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    push_varint(&mut out, state.len());
+    let mut i = 0;
+    while i < state.len() {
+        let start = i;
+        while i < state.len() && state[i] == base_at(i) {
+            i += 1;
+        }
+        let same = i - start;
+        let start = i;
+        //a short run of equal bytes costs more to encode than to copy
+        while i < state.len()
+            && (state[i] != base_at(i)
+                || (i + 2 < state.len()
+                    && (state[i + 1] != base_at(i + 1) || state[i + 2] != base_at(i + 2))))
+        {
+            i += 1;
+        }
+        push_varint(&mut out, same);
+        push_varint(&mut out, i - start);
+        out.extend((start..i).map(|j| state[j] ^ base_at(j)));
+    }
+    out
+}
+pub fn decode(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let len = read_varint(delta, &mut position);
+    let mut state: Vec<u8> = (0..len)
+        .map(|i| base.get(i).copied().unwrap_or(0))
+        .collect();
+    let mut i = 0;
+    while position < delta.len() {
+        i += read_varint(delta, &mut position);
+        let changed = read_varint(delta, &mut position);
+        for _ in 0..changed {
+            if let (Some(byte), Some(xor)) = (state.get_mut(i), delta.get(position)) {
+                *byte ^= xor;
+            }
+            i += 1;
+            position += 1;
+        }
+    }
+    state
+}
+impl Rewind {
+    pub fn new(interval: u64, group_size: usize, budget: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            group_size: group_size.max(1),
+            budget: budget,
+            groups: VecDeque::new(),
+            keyframe: Vec::new(),
+            inputs: VecDeque::new(),
+            inputs_start: 0,
+            frame: 0,
+            used: 0,
+        }
+    }
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.keyframe.clear();
+        self.inputs.clear();
+        self.inputs_start = 0;
+        self.frame = 0;
+        self.used = 0;
+    }
+    //call once after every emulated frame
+    pub fn push(&mut self, console: &mut Console) {
+        if self.groups.is_empty() {
+            //nothing to replay from yet, start with the state before this frame's input
+            self.inputs_start = self.frame + 1;
+        } else {
+            self.inputs.push_back(console.cpu.bus.joypad1.button_status);
+            self.used += 1;
+        }
+        self.frame += 1;
+        if self.groups.is_empty() || self.frame % self.interval == 0 {
+            self.capture(console);
+        }
+        while self.used > self.budget && self.groups.len() > 1 {
+            self.drop_oldest();
+        }
+    }
+    fn capture(&mut self, console: &mut Console) {
+        let state = console.save_state();
+        let new_group = match self.groups.back() {
+            Some(group) => {
+                group.deltas.len() + 1 >= self.group_size || state.len() != self.keyframe.len()
+            }
+            None => true,
+        };
+        if new_group {
+            let keyframe = RewindPoint {
+                frame: self.frame,
+                data: encode(&[], &state),
+            };
+            self.used += keyframe.data.len();
+            self.groups.push_back(RewindGroup {
+                keyframe: keyframe,
+                deltas: Vec::new(),
+            });
+            self.keyframe = state;
+        } else {
+            let delta = RewindPoint {
+                frame: self.frame,
+                data: encode(&self.keyframe, &state),
+            };
+            self.used += delta.data.len();
+            self.groups.back_mut().unwrap().deltas.push(delta);
+        }
+    }
+    fn drop_oldest(&mut self) {
+        if let Some(group) = self.groups.pop_front() {
+            self.used -= group.keyframe.data.len();
+            self.used -= group.deltas.iter().map(|d| d.data.len()).sum::<usize>();
+        }
+        if let Some(group) = self.groups.front() {
+            while self.inputs_start < group.keyframe.frame {
+                self.inputs.pop_front();
+                self.inputs_start += 1;
+                self.used -= 1;
+            }
+        }
+    }
+    //forgets everything taken after the given frame
+    fn truncate(&mut self, frame: u64) {
+        while let Some(group) = self.groups.back_mut() {
+            while group.deltas.last().map_or(false, |d| d.frame > frame) {
+                self.used -= group.deltas.pop().unwrap().data.len();
+            }
+            if group.keyframe.frame <= frame {
+                break;
+            }
+            self.used -= group.keyframe.data.len();
+            self.groups.pop_back();
+        }
+        if let Some(group) = self.groups.back() {
+            self.keyframe = decode(&[], &group.keyframe.data);
+        }
+        let kept = frame.saturating_sub(self.inputs_start) as usize;
+        while self.inputs.len() > kept {
+            self.inputs.pop_back();
+            self.used -= 1;
+        }
+        self.frame = frame;
+    }
+    /*
+    Moves the console back one frame. The newest state taken before that frame is
+    restored and the frames after it are run again, at least one so the frame buffer
+    is redrawn. Returns false once the history is used up.
+     */
+    pub fn step_back(&mut self, console: &mut Console) -> bool {
+        let target = match self.frame.checked_sub(1) {
+            Some(target) => target,
+            None => return false,
+        };
+        let mut found = None;
+        for group in self.groups.iter().rev() {
+            let point = group.deltas.iter().rev().find(|d| d.frame < target);
+            if let Some(delta) = point {
+                found = Some((
+                    delta.frame,
+                    decode(&decode(&[], &group.keyframe.data), &delta.data),
+                ));
+                break;
+            }
+            if group.keyframe.frame < target {
+                found = Some((group.keyframe.frame, decode(&[], &group.keyframe.data)));
+                break;
+            }
+        }
+        let (start, state) = match found {
+            Some(found) => found,
+            None => return false,
+        };
+        if console.load_state(&state).is_err() {
+            return false;
+        }
+        let held = console.cpu.bus.joypad1.button_status;
+        for frame in start..target {
+            let input = self.inputs[(frame - self.inputs_start) as usize];
+            console.set_buttons(input);
+            console.step_frame();
+        }
+        console.set_buttons(held);
+        //replayed audio would play forwards, drop it
+        console.take_audio_samples();
+        self.truncate(target);
+        true
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Rom;
+    #[test]
+    fn decode_undoes_encode() {
+        let base: Vec<u8> = (0..200).map(|i| (i * 7) as u8).collect();
+        let mut state = base.clone();
+        state[0] ^= 1;
+        state[50..60].iter_mut().for_each(|b| *b = 0xaa);
+        state[199] = 0;
+        assert_eq!(decode(&base, &encode(&base, &state)), state);
+        //longer than the base, and long runs that need multi-byte varints
+        state.extend((0..1000).map(|i| (i % 5) as u8));
+        assert_eq!(decode(&base, &encode(&base, &state)), state);
+        //shorter than the base
+        assert_eq!(decode(&base, &encode(&base, &state[..20])), &state[..20]);
+        //keyframes are encoded against nothing
+        assert_eq!(decode(&[], &encode(&[], &state)), state);
+        assert_eq!(decode(&[], &encode(&[], &[])), Vec::<u8>::new());
+    }
+    //NROM image spinning in a loop at $8000
+    fn console() -> Console {
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1];
+        raw.resize(16, 0);
+        let mut prg = vec![0xea; 0x4000];
+        prg[..3].copy_from_slice(&[0x4c, 0x00, 0x80]);
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        Console::new(Rom::new(&raw).unwrap()).unwrap()
+    }
+    #[test]
+    fn step_back_after_dropping_old_groups() {
+        let mut console = console();
+        let keyframe_size = encode(&[], &console.save_state()).len();
+        //room for a few groups, so the oldest ones keep being dropped
+        let mut rewind = Rewind::new(2, 3, keyframe_size * 8);
+        for frame in 0..60 {
+            console.set_buttons(frame as u8);
+            console.step_frame();
+            rewind.push(&mut console);
+        }
+        assert!(rewind.groups.len() > 1 && rewind.groups.len() < 10);
+        let oldest = rewind.groups.front().unwrap().keyframe.frame;
+        assert_eq!(rewind.inputs_start, oldest);
+        let mut steps = 0;
+        while rewind.step_back(&mut console) {
+            steps += 1;
+            assert_eq!(
+                rewind.inputs.len() as u64,
+                rewind.frame - rewind.inputs_start
+            );
+        }
+        //every frame after the oldest state can be stepped back to
+        assert_eq!(steps, 60 - oldest - 1);
+        assert_eq!(rewind.frame, oldest + 1);
+    }
+}
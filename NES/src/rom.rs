@@ -1,45 +1,499 @@
+use std::fmt;
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 const PRG_ROM_PAGE_SIZE: usize = 16384;
 const CHR_ROM_PAGE_SIZE: usize = 8192;
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
     Fourscreen,
+    //all four nametables show the first or second 1K of vram
+    SingleScreenLower,
+    SingleScreenUpper,
+    //vram page (0 or 1) for each of the four nametables, set by MMC5
+    Custom([u8; 4]),
 }
-pub struct Rom {
-    pub prg_rom: Vec<u8>,
-    pub chr_rom: Vec<u8>,
-    pub mapper: u8,
-    pub screen_mirroring: Mirroring,
+/*
+The 16 byte header. iNES 1.0 fills bytes 4-7, NES 2.0 (bits 2-3 of byte 7 set to 10)
+extends it:
+4/5   prg-rom size in 16K / chr-rom size in 8K units, low 8 bits
+6     MMMM FTBV  mapper low nibble, four screen, trainer, battery, vertical mirroring
+7     MMMM 10CC  mapper bits 4-7, NES 2.0 id, console type
+8     SSSS MMMM  submapper, mapper bits 8-11
+9     CCCC PPPP  chr-rom / prg-rom size high nibbles. $F means the low byte is
+                 EEEE EEMM and the size is 2^E * (MM*2+1) bytes
+10    pppp PPPP  prg-nvram / prg-ram shift counts, size is 64 << shift (0: none)
+11    cccc CCCC  chr-nvram / chr-ram shift counts
+12    ---- --TT  timing: 0 NTSC, 1 PAL, 2 multi-region, 3 Dendy
+13    HHHH PPPP  Vs. System hardware and ppu type, or the extended console type
+14    ---- --RR  number of miscellaneous roms after the chr data
+15    --DD DDDD  default expansion device
+ */
+//everything that can be wrong with a rom file, offsets are bytes into the file
+#[derive(Debug, PartialEq, Clone)]
+pub enum RomError {
+    //the file does not start with "NES" $1A
+    BadMagic {
+        found: Vec<u8>,
+    },
+    TruncatedHeader {
+        file_len: usize,
+    },
+    //a section runs past the end of the file, start and end are where it should be
+    TruncatedTrainer {
+        start: usize,
+        end: usize,
+        file_len: usize,
+    },
+    TruncatedPrgRom {
+        start: usize,
+        end: usize,
+        file_len: usize,
+    },
+    TruncatedChrRom {
+        start: usize,
+        end: usize,
+        file_len: usize,
+    },
+    //less prg-rom than one 16K bank, which every board needs to bank switch
+    PrgRomTooSmall {
+        size: usize,
+    },
+    //chr-rom that does not fill the ppu's 8K of pattern tables, no chr-rom means chr-ram
+    ChrRomTooSmall {
+        size: usize,
+    },
+    UnsupportedMapper {
+        offset: usize,
+        mapper: u16,
+        submapper: u8,
+    },
+    //bytes left over after the last section the header describes
+    TrailingData {
+        offset: usize,
+        len: usize,
+    },
+    //old dumping tools wrote their name over bytes 7-15, which breaks the mapper number
+    DiskDudeHeader {
+        offset: usize,
+    },
 }
-impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not an iNES file".to_string());
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::BadMagic { found } => {
+                write!(f, "File is not an iNES file, starts with {:02X?}", found)
+            }
+            RomError::TruncatedHeader { file_len } => {
+                write!(f, "File is {} bytes, too short for a header", file_len)
+            }
+            RomError::TruncatedTrainer {
+                start,
+                end,
+                file_len,
+            } => write!(
+                f,
+                "Trainer at {:#x}-{:#x} runs past the end of the file ({:#x} bytes)",
+                start, end, file_len
+            ),
+            RomError::TruncatedPrgRom {
+                start,
+                end,
+                file_len,
+            } => write!(
+                f,
+                "PRG-ROM at {:#x}-{:#x} runs past the end of the file ({:#x} bytes)",
+                start, end, file_len
+            ),
+            RomError::TruncatedChrRom {
+                start,
+                end,
+                file_len,
+            } => write!(
+                f,
+                "CHR-ROM at {:#x}-{:#x} runs past the end of the file ({:#x} bytes)",
+                start, end, file_len
+            ),
+            RomError::PrgRomTooSmall { size } => {
+                write!(f, "{} bytes of prg-rom is less than one bank", size)
+            }
+            RomError::ChrRomTooSmall { size } => {
+                write!(f, "{} bytes of chr-rom is less than one bank", size)
+            }
+            RomError::UnsupportedMapper {
+                offset,
+                mapper,
+                submapper,
+            } => write!(
+                f,
+                "Mapper {}.{} (header byte {}) is not supported",
+                mapper, submapper, offset
+            ),
+            RomError::TrailingData { offset, len } => {
+                write!(f, "{} bytes of unexpected data at {:#x}", len, offset)
+            }
+            RomError::DiskDudeHeader { offset } => {
+                write!(
+                    f,
+                    "Header is polluted with \"DiskDude!\" at byte {}",
+                    offset
+                )
+            }
+        }
+    }
+}
+impl std::error::Error for RomError {}
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem { ppu: u8, hardware: u8 },
+    Playchoice10,
+    //byte 13 low nibble, famiclones and the like
+    Extended(u8),
+}
+#[derive(Debug, Clone, Copy)]
+pub struct RomHeader {
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    //bytes of chr-ram on the board, 0 when the pattern tables are rom
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+}
+impl RomHeader {
+    pub fn parse(raw: &[u8]) -> Result<RomHeader, RomError> {
+        if raw.len() < NES_TAG.len() || raw[0..4] != NES_TAG {
+            return Err(RomError::BadMagic {
+                found: raw[..raw.len().min(4)].to_vec(),
+            });
+        }
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::TruncatedHeader {
+                file_len: raw.len(),
+            });
         }
-        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
-        let ines_ver = (raw[7] >> 2) & 0b11;
-        if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+        let nes2 = (raw[7] >> 2) & 0b11 == 2;
+        if !nes2 && &raw[7..HEADER_SIZE] == b"DiskDude!" {
+            return Err(RomError::DiskDudeHeader { offset: 7 });
         }
         let four_screen = raw[6] & 0b1000 != 0;
         let vertical_mirroring = raw[6] & 0b1 != 0;
-        let screen_mirroring = match (four_screen, vertical_mirroring) {
+        let mirroring = match (four_screen, vertical_mirroring) {
             (true, _) => Mirroring::Fourscreen,
             (false, true) => Mirroring::Vertical,
             (false, false) => Mirroring::Horizontal,
         };
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
-        let skip_trainer = raw[6] & 0b100 != 0;
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
-        let chr_rom_start = prg_rom_start + prg_rom_size;
+        let mut header = RomHeader {
+            nes2: nes2,
+            mapper: ((raw[7] & 0b1111_0000) | (raw[6] >> 4)) as u16,
+            submapper: 0,
+            prg_rom_size: raw[4] as usize * PRG_ROM_PAGE_SIZE,
+            chr_rom_size: raw[5] as usize * CHR_ROM_PAGE_SIZE,
+            //iNES 1.0 has no ram sizes, boards get the usual 8K of prg-ram and chr-ram
+            //when there is no chr-rom
+            prg_ram_size: 0x2000,
+            prg_nvram_size: 0,
+            chr_ram_size: if raw[5] == 0 { CHR_ROM_PAGE_SIZE } else { 0 },
+            chr_nvram_size: 0,
+            mirroring: mirroring,
+            battery: raw[6] & 0b10 != 0,
+            trainer: raw[6] & 0b100 != 0,
+            timing: Timing::Ntsc,
+            //iNES 1.0 has a flag each for Vs. System and PlayChoice-10
+            console_type: match raw[7] & 0b11 {
+                0 => ConsoleType::Nes,
+                2 => ConsoleType::Playchoice10,
+                _ => ConsoleType::VsSystem {
+                    ppu: 0,
+                    hardware: 0,
+                },
+            },
+            misc_roms: 0,
+            expansion_device: 0,
+        };
+        if !nes2 {
+            if header.battery {
+                header.prg_nvram_size = header.prg_ram_size;
+                header.prg_ram_size = 0;
+            }
+            return Ok(header);
+        }
+        let shifted = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+        header.mapper |= (raw[8] as u16 & 0x0f) << 8;
+        header.submapper = raw[8] >> 4;
+        header.prg_rom_size = rom_size(raw[4], raw[9] & 0x0f, PRG_ROM_PAGE_SIZE);
+        header.chr_rom_size = rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE);
+        header.prg_ram_size = shifted(raw[10] & 0x0f);
+        header.prg_nvram_size = shifted(raw[10] >> 4);
+        header.chr_ram_size = shifted(raw[11] & 0x0f);
+        header.chr_nvram_size = shifted(raw[11] >> 4);
+        header.console_type = match raw[7] & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem {
+                ppu: raw[13] & 0x0f,
+                hardware: raw[13] >> 4,
+            },
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(raw[13] & 0x0f),
+        };
+        header.timing = match raw[12] & 0b11 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultiRegion,
+            _ => Timing::Dendy,
+        };
+        header.misc_roms = raw[14] & 0b11;
+        header.expansion_device = raw[15] & 0b0011_1111;
+        Ok(header)
+    }
+}
+//NES 2.0 rom size from the low byte and high nibble, in bytes
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0f {
+        let exponent = lsb >> 2;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        (1usize << exponent).saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+pub struct Rom {
+    pub header: RomHeader,
+    //512 bytes copied to $7000 at power on, empty when the header has no trainer flag
+    pub trainer: Vec<u8>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+}
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        let header = RomHeader::parse(raw)?;
+        if header.prg_rom_size < PRG_ROM_PAGE_SIZE {
+            return Err(RomError::PrgRomTooSmall {
+                size: header.prg_rom_size,
+            });
+        }
+        if header.chr_rom_size > 0 && header.chr_rom_size < CHR_ROM_PAGE_SIZE {
+            return Err(RomError::ChrRomTooSmall {
+                size: header.chr_rom_size,
+            });
+        }
+        let file_len = raw.len();
+        let prg_rom_start = HEADER_SIZE + if header.trainer { TRAINER_SIZE } else { 0 };
+        if prg_rom_start > file_len {
+            return Err(RomError::TruncatedTrainer {
+                start: HEADER_SIZE,
+                end: prg_rom_start,
+                file_len: file_len,
+            });
+        }
+        let chr_rom_start = prg_rom_start.saturating_add(header.prg_rom_size);
+        if chr_rom_start > file_len {
+            return Err(RomError::TruncatedPrgRom {
+                start: prg_rom_start,
+                end: chr_rom_start,
+                file_len: file_len,
+            });
+        }
+        let chr_rom_end = chr_rom_start.saturating_add(header.chr_rom_size);
+        if chr_rom_end > file_len {
+            return Err(RomError::TruncatedChrRom {
+                start: chr_rom_start,
+                end: chr_rom_end,
+                file_len: file_len,
+            });
+        }
+        //NES 2.0 misc roms and the PlayChoice-10 hint screen legitimately follow the chr data
+        let extra_roms = header.misc_roms > 0 || header.console_type == ConsoleType::Playchoice10;
+        if chr_rom_end < file_len && !extra_roms {
+            return Err(RomError::TrailingData {
+                offset: chr_rom_end,
+                len: file_len - chr_rom_end,
+            });
+        }
         Ok(Rom {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
-            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
-            mapper: mapper,
-            screen_mirroring: screen_mirroring,
+            trainer: raw[HEADER_SIZE..prg_rom_start].to_vec(),
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..chr_rom_end].to_vec(),
+            header: header,
         })
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    //iNES 1.0 header with the given flags 6 byte, followed by `body` zero bytes
+    fn image(prg_pages: u8, chr_pages: u8, flags6: u8, body: usize) -> Vec<u8> {
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, prg_pages, chr_pages, flags6];
+        raw.resize(HEADER_SIZE + body, 0);
+        raw
+    }
+    #[test]
+    fn accepts_a_well_formed_image() {
+        let rom = Rom::new(&image(1, 1, 0, 0x4000 + 0x2000)).unwrap();
+        assert_eq!(rom.prg_rom.len(), 0x4000);
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+        assert!(rom.trainer.is_empty());
+    }
+    #[test]
+    fn bad_magic() {
+        assert_eq!(
+            Rom::new(b"NES\0rest").err(),
+            Some(RomError::BadMagic {
+                found: b"NES\0".to_vec()
+            })
+        );
+        assert_eq!(
+            Rom::new(b"NE").err(),
+            Some(RomError::BadMagic {
+                found: b"NE".to_vec()
+            })
+        );
+    }
+    #[test]
+    fn short_header() {
+        let raw = &image(1, 1, 0, 0)[..10];
+        assert_eq!(
+            Rom::new(raw).err(),
+            Some(RomError::TruncatedHeader { file_len: 10 })
+        );
+    }
+    #[test]
+    fn truncated_trainer() {
+        assert_eq!(
+            Rom::new(&image(1, 0, 0b100, 0x100)).err(),
+            Some(RomError::TruncatedTrainer {
+                start: 16,
+                end: 16 + 512,
+                file_len: 16 + 0x100,
+            })
+        );
+    }
+    #[test]
+    fn truncated_prg_rom() {
+        assert_eq!(
+            Rom::new(&image(2, 1, 0, 0x4000)).err(),
+            Some(RomError::TruncatedPrgRom {
+                start: 16,
+                end: 16 + 0x8000,
+                file_len: 16 + 0x4000,
+            })
+        );
+        //the prg data starts after the trainer
+        assert_eq!(
+            Rom::new(&image(1, 0, 0b100, 512 + 0x1000)).err(),
+            Some(RomError::TruncatedPrgRom {
+                start: 16 + 512,
+                end: 16 + 512 + 0x4000,
+                file_len: 16 + 512 + 0x1000,
+            })
+        );
+    }
+    #[test]
+    fn truncated_chr_rom() {
+        assert_eq!(
+            Rom::new(&image(1, 1, 0, 0x4000 + 0x100)).err(),
+            Some(RomError::TruncatedChrRom {
+                start: 16 + 0x4000,
+                end: 16 + 0x6000,
+                file_len: 16 + 0x4100,
+            })
+        );
+    }
+    #[test]
+    fn prg_rom_smaller_than_a_bank() {
+        assert_eq!(
+            Rom::new(&image(0, 1, 0, 0x2000)).err(),
+            Some(RomError::PrgRomTooSmall { size: 0 })
+        );
+        //NES 2.0 exponent form, 2^13 bytes
+        let mut raw = image(0x34, 1, 0, 0x2000 + 0x2000);
+        raw[7] = 0b1000;
+        raw[9] = 0x0f;
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::PrgRomTooSmall { size: 0x2000 })
+        );
+    }
+    #[test]
+    fn chr_rom_smaller_than_a_bank() {
+        //NES 2.0 exponent form, 2^12 bytes
+        let mut raw = image(1, 0x30, 0, 0x4000 + 0x1000);
+        raw[7] = 0b1000;
+        raw[9] = 0xf0;
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::ChrRomTooSmall { size: 0x1000 })
+        );
+    }
+    #[test]
+    fn trailing_data() {
+        assert_eq!(
+            Rom::new(&image(1, 1, 0, 0x6000 + 3)).err(),
+            Some(RomError::TrailingData {
+                offset: 16 + 0x6000,
+                len: 3,
+            })
+        );
+    }
+    #[test]
+    fn disk_dude_header() {
+        let mut raw = image(1, 1, 0, 0x6000);
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        assert_eq!(
+            Rom::new(&raw).err(),
+            Some(RomError::DiskDudeHeader { offset: 7 })
+        );
+    }
+    #[test]
+    fn unsupported_mapper() {
+        //mapper 9 (MMC2), low nibble in byte 6 and high nibble in byte 7
+        let rom = Rom::new(&image(1, 1, 0x90, 0x6000)).unwrap();
+        match crate::mapper::new(rom) {
+            Err(e) => assert_eq!(
+                e,
+                RomError::UnsupportedMapper {
+                    offset: 6,
+                    mapper: 9,
+                    submapper: 0,
+                }
+            ),
+            Ok(_) => panic!("mapper 9 should not be supported"),
+        }
+    }
+    #[test]
+    fn prg_ram_only_when_the_header_asks_for_it() {
+        //NES 2.0 UxROM, byte 10 gives no prg-ram
+        let mut raw = image(1, 0, 0x20, 0x4000);
+        raw[7] = 0b1000;
+        let mapper = crate::mapper::new(Rom::new(&raw).unwrap()).unwrap();
+        mapper.borrow_mut().write_prg(0x6000, 0x55);
+        assert_eq!(mapper.borrow_mut().read_prg(0x6000), 0);
+        assert!(mapper.borrow_mut().prg_ram().is_none());
+        //a trainer still gets ram to load into at $7000
+        let mut raw = image(1, 0, 0x24, 512 + 0x4000);
+        raw[7] = 0b1000;
+        raw[16] = 0xaa;
+        let mapper = crate::mapper::new(Rom::new(&raw).unwrap()).unwrap();
+        assert_eq!(mapper.borrow_mut().read_prg(0x7000), 0xaa);
+    }
+}
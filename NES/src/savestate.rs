@@ -0,0 +1,385 @@
+use crate::rom::Mirroring;
+use std::fmt;
+use std::ops::RangeInclusive;
+/*
+Save state file layout, all numbers little endian:
+  0   "NESS"   magic
+  4   u16      format version, bumped whenever a snapshot() changes what it writes
+  6   u64      fnv-1a hash of the rom's prg and chr data
+  14  ...      cpu registers, then the bus: ram, cycle counters, joypad, ppu, apu and
+               finally the cartridge
+Each component writes its fields in a fixed order through one snapshot() function
+that is used for both saving and loading, so the two can not drift apart.
+ */
+pub const STATE_MAGIC: [u8; 4] = *b"NESS";
+pub const STATE_VERSION: u16 = 1;
+pub const STATE_HEADER_SIZE: usize = 14;
+#[derive(Debug, PartialEq, Clone)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    //the state was taken with a different game
+    WrongRom { expected: u64, found: u64 },
+    //the data ends early, or runs on past the cartridge
+    BadLength { expected: usize, found: usize },
+    //a field holds a value the machine can not be in, offset is from the start of the state
+    BadValue { offset: usize },
+}
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "Save state version {} is not supported (expected {})",
+                version, STATE_VERSION
+            ),
+            StateError::WrongRom { expected, found } => write!(
+                f,
+                "Save state is for rom {:016x}, this rom is {:016x}",
+                found, expected
+            ),
+            StateError::BadLength { expected, found } => {
+                write!(f, "Save state is {} bytes, expected {}", found, expected)
+            }
+            StateError::BadValue { offset } => {
+                write!(f, "Save state has an impossible value at byte {}", offset)
+            }
+        }
+    }
+}
+impl std::error::Error for StateError {}
+//64 bit fnv-1a, identifies roms in save states and movies
+pub fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+/*
+Either collects bytes (saving) or hands them back out (loading). Reading past the
+end yields zeros and sets overrun, an out of range value is clamped and its position
+kept in bad_value, callers check both once at the end.
+ */
+pub struct StateStream {
+    pub data: Vec<u8>,
+    pub position: usize,
+    pub loading: bool,
+    pub overrun: bool,
+    pub bad_value: Option<usize>,
+}
+impl StateStream {
+    pub fn saver() -> Self {
+        StateStream {
+            data: Vec::new(),
+            position: 0,
+            loading: false,
+            overrun: false,
+            bad_value: None,
+        }
+    }
+    pub fn loader(data: &[u8]) -> Self {
+        StateStream {
+            data: data.to_vec(),
+            position: 0,
+            loading: true,
+            overrun: false,
+            bad_value: None,
+        }
+    }
+    fn raw<const N: usize>(&mut self, value: [u8; N]) -> [u8; N] {
+        if !self.loading {
+            self.data.extend_from_slice(&value);
+            return value;
+        }
+        let mut out = [0; N];
+        match self.data.get(self.position..self.position + N) {
+            Some(bytes) => out.copy_from_slice(bytes),
+            None => self.overrun = true,
+        }
+        self.position += N;
+        out
+    }
+    pub fn u8(&mut self, value: &mut u8) {
+        *value = self.raw([*value])[0];
+    }
+    /*
+    For fields the machine indexes a table with, shifts by, decrements or counts up
+    to a limit with: a loaded value outside the range is clamped into it and rejected.
+     */
+    pub fn u8_below(&mut self, value: &mut u8, limit: u8) {
+        self.u8_range(value, 0..=limit - 1);
+    }
+    pub fn u8_range(&mut self, value: &mut u8, range: RangeInclusive<u8>) {
+        let position = self.position;
+        self.u8(value);
+        self.check(position, value, range);
+    }
+    pub fn bool(&mut self, value: &mut bool) {
+        *value = self.raw([*value as u8])[0] != 0;
+    }
+    pub fn u16(&mut self, value: &mut u16) {
+        *value = u16::from_le_bytes(self.raw(value.to_le_bytes()));
+    }
+    pub fn u16_range(&mut self, value: &mut u16, range: RangeInclusive<u16>) {
+        let position = self.position;
+        self.u16(value);
+        self.check(position, value, range);
+    }
+    //a value that must be one of the entries of a period table
+    pub fn u16_of(&mut self, value: &mut u16, table: &[u16]) {
+        let position = self.position;
+        self.u16(value);
+        if self.loading && !table.contains(value) {
+            self.reject(position);
+            *value = table[0];
+        }
+    }
+    pub fn i16(&mut self, value: &mut i16) {
+        *value = i16::from_le_bytes(self.raw(value.to_le_bytes()));
+    }
+    pub fn i16_range(&mut self, value: &mut i16, range: RangeInclusive<i16>) {
+        let position = self.position;
+        self.i16(value);
+        self.check(position, value, range);
+    }
+    pub fn u32(&mut self, value: &mut u32) {
+        *value = u32::from_le_bytes(self.raw(value.to_le_bytes()));
+    }
+    pub fn u32_range(&mut self, value: &mut u32, range: RangeInclusive<u32>) {
+        let position = self.position;
+        self.u32(value);
+        self.check(position, value, range);
+    }
+    pub fn u64(&mut self, value: &mut u64) {
+        *value = u64::from_le_bytes(self.raw(value.to_le_bytes()));
+    }
+    //stored as 64 bits so states move between 32 and 64 bit builds
+    pub fn usize(&mut self, value: &mut usize) {
+        let mut wide = *value as u64;
+        self.u64(&mut wide);
+        *value = wide as usize;
+    }
+    pub fn usize_below(&mut self, value: &mut usize, limit: usize) {
+        let position = self.position;
+        self.usize(value);
+        self.check(position, value, 0..=limit - 1);
+    }
+    fn check<T: PartialOrd + Copy>(
+        &mut self,
+        position: usize,
+        value: &mut T,
+        range: RangeInclusive<T>,
+    ) {
+        if self.loading && !range.contains(value) {
+            self.reject(position);
+            *value = if *value < *range.start() {
+                *range.start()
+            } else {
+                *range.end()
+            };
+        }
+    }
+    fn reject(&mut self, position: usize) {
+        if self.bad_value.is_none() {
+            self.bad_value = Some(position);
+        }
+    }
+    pub fn f32(&mut self, value: &mut f32) {
+        *value = f32::from_le_bytes(self.raw(value.to_le_bytes()));
+    }
+    //fixed size block, the length comes from the machine and is not stored
+    pub fn bytes(&mut self, value: &mut [u8]) {
+        if !self.loading {
+            self.data.extend_from_slice(value);
+            return;
+        }
+        match self.data.get(self.position..self.position + value.len()) {
+            Some(bytes) => value.copy_from_slice(bytes),
+            None => self.overrun = true,
+        }
+        self.position += value.len();
+    }
+    pub fn option_u8(&mut self, value: &mut Option<u8>) {
+        let mut present = value.is_some();
+        let mut data = value.unwrap_or(0);
+        self.bool(&mut present);
+        self.u8(&mut data);
+        *value = if present { Some(data) } else { None };
+    }
+    pub fn mirroring(&mut self, value: &mut Mirroring) {
+        let (mut kind, mut pages) = match *value {
+            Mirroring::Vertical => (0, [0; 4]),
+            Mirroring::Horizontal => (1, [0; 4]),
+            Mirroring::Fourscreen => (2, [0; 4]),
+            Mirroring::SingleScreenLower => (3, [0; 4]),
+            Mirroring::SingleScreenUpper => (4, [0; 4]),
+            Mirroring::Custom(pages) => (5, pages),
+        };
+        self.u8_below(&mut kind, 6);
+        //custom pages pick one of the two 1K pages of console vram
+        for page in pages.iter_mut() {
+            self.u8_below(page, 2);
+        }
+        *value = match kind {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::Fourscreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            _ => Mirroring::Custom(pages),
+        };
+    }
+}
+//anything that is part of the machine's state
+pub trait Snapshot {
+    fn snapshot(&mut self, s: &mut StateStream);
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::dmc::DMC;
+    use crate::apu::noise::Noise;
+    use crate::apu::pulse::Pulse;
+    use crate::apu::triangle::Triangle;
+    use crate::mapper::fme7::FME7;
+    use crate::mapper::namco163::Namco163;
+    use crate::mapper::vrc6::VRC6;
+    use crate::mapper::vrc_irq::VrcIrq;
+    use crate::rom::Rom;
+    use crate::Console;
+    //16K of prg spinning in a loop at $8000 and 8K of chr
+    fn rom(mapper: u8) -> Rom {
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, mapper << 4, mapper & 0xf0];
+        raw.resize(16, 0);
+        let mut prg = vec![0xea; 0x4000];
+        prg[..3].copy_from_slice(&[0x4c, 0x00, 0x80]);
+        prg[0x3ffc..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        Rom::new(&raw).unwrap()
+    }
+    fn console() -> Console {
+        let mut console = Console::new(rom(0)).unwrap();
+        for _ in 0..3 {
+            console.step_frame();
+        }
+        console
+    }
+    #[test]
+    fn save_load_save_is_identical() {
+        let mut console = console();
+        let state = console.save_state();
+        console.step_frame();
+        assert_ne!(console.save_state(), state);
+        console.load_state(&state).unwrap();
+        assert_eq!(console.save_state(), state);
+    }
+    #[test]
+    fn truncated_or_overlong_states_are_rejected() {
+        let mut console = console();
+        let state = console.save_state();
+        let short = &state[..state.len() - 1];
+        assert_eq!(
+            console.load_state(short),
+            Err(StateError::BadLength {
+                expected: state.len(),
+                found: state.len() - 1,
+            })
+        );
+        let mut long = state.clone();
+        long.push(0);
+        assert_eq!(
+            console.load_state(&long),
+            Err(StateError::BadLength {
+                expected: state.len(),
+                found: state.len() + 1,
+            })
+        );
+        assert_eq!(console.save_state(), state);
+    }
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let mut console = console();
+        console.cpu.bus.ppu.sprite_count = 3;
+        let state = console.save_state();
+        console.cpu.bus.ppu.sprite_count = 5;
+        let other = console.save_state();
+        let offset = (0..state.len()).find(|&i| state[i] != other[i]).unwrap();
+        let mut bad = state.clone();
+        bad[offset] = 9;
+        assert_eq!(
+            console.load_state(&bad),
+            Err(StateError::BadValue { offset: offset })
+        );
+        assert_eq!(console.cpu.bus.ppu.sprite_count, 5);
+    }
+    //finds a field by saving it as 1 and then 2, overwrites it with bad and checks the
+    //load is rejected at that offset
+    fn assert_rejected<T: Snapshot>(thing: &mut T, set: impl Fn(&mut T, u8), bad: &[u8]) {
+        let mut first = StateStream::saver();
+        set(thing, 1);
+        thing.snapshot(&mut first);
+        let mut second = StateStream::saver();
+        set(thing, 2);
+        thing.snapshot(&mut second);
+        let (mut data, other) = (first.data, second.data);
+        let offset = (0..data.len()).find(|&i| data[i] != other[i]).unwrap();
+        data[offset..offset + bad.len()].copy_from_slice(bad);
+        let mut loader = StateStream::loader(&data);
+        thing.snapshot(&mut loader);
+        assert_eq!(loader.bad_value, Some(offset));
+    }
+    #[test]
+    fn pulse_values_are_range_checked() {
+        assert_rejected(&mut Pulse::new(1), |p, v| p.sweep_shift = v, &[8]);
+        assert_rejected(&mut Pulse::new(1), |p, v| p.sequence = v, &[8]);
+        assert_rejected(&mut Pulse::new(1), |p, v| p.envelope.volume = v, &[16]);
+        assert_rejected(
+            &mut Pulse::new(1),
+            |p, v| p.timer_period = v as u16,
+            &[0x00, 0x08],
+        );
+    }
+    #[test]
+    fn triangle_values_are_range_checked() {
+        assert_rejected(&mut Triangle::new(), |t, v| t.sequence = v, &[32]);
+    }
+    #[test]
+    fn noise_values_are_range_checked() {
+        assert_rejected(&mut Noise::new(), |n, v| n.timer_period = v as u16, &[0, 0]);
+        assert_rejected(&mut Noise::new(), |n, v| n.envelope.volume = v, &[16]);
+    }
+    #[test]
+    fn dmc_values_are_range_checked() {
+        assert_rejected(&mut DMC::new(), |d, v| d.timer_period = v as u16, &[0, 0]);
+        assert_rejected(&mut DMC::new(), |d, v| d.bits_remaining = v, &[0]);
+        assert_rejected(&mut DMC::new(), |d, v| d.bits_remaining = v, &[9]);
+    }
+    #[test]
+    fn expansion_audio_values_are_range_checked() {
+        assert_rejected(&mut VRC6::new(rom(24)), |m, v| m.frequency_shift = v, &[9]);
+        assert_rejected(&mut FME7::new(rom(69)), |m, v| m.audio_divider = v, &[0xff]);
+        assert_rejected(&mut FME7::new(rom(69)), |m, v| m.noise_timer = v, &[32]);
+        assert_rejected(
+            &mut Namco163::new(rom(19)),
+            |m, v| m.channel_cycles = v,
+            &[0xff],
+        );
+        assert_rejected(&mut VrcIrq::new(), |i, v| i.prescaler = v as i16, &[0, 0]);
+    }
+    #[test]
+    fn custom_mirroring_pages_are_range_checked() {
+        let mut saver = StateStream::saver();
+        saver.mirroring(&mut Mirroring::Custom([0, 1, 1, 0]));
+        let mut data = saver.data;
+        data[3] = 2;
+        let mut loader = StateStream::loader(&data);
+        let mut mirroring = Mirroring::Vertical;
+        loader.mirroring(&mut mirroring);
+        assert_eq!(loader.bad_value, Some(3));
+        assert_eq!(mirroring, Mirroring::Custom([0, 1, 1, 0]));
+    }
+}
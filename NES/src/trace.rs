@@ -0,0 +1,142 @@
+use crate::bus::Memory;
+use crate::cpu::AddressingMode;
+use crate::cpu::CPU;
+use crate::opcodes;
+use std::collections::HashMap;
+
+//nestest.log style line for the instruction at the program counter
+pub fn trace(cpu: &mut CPU) -> String {
+    let ref opscodes: HashMap<u8, &'static opcodes::Opcode> = *opcodes::OPCODES_MAP;
+    let program_counter = cpu.program_counter;
+    let register_a = cpu.register_a;
+    let register_x = cpu.register_x;
+    let register_y = cpu.register_y;
+    let p = cpu.status;
+    let sp = cpu.stack_ptr;
+    let opscode = cpu.mem_read(program_counter);
+    let opscode_data = opscodes.get(&opscode).unwrap();
+    let registers = format!(
+        "A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
+        register_a, register_x, register_y, p, sp
+    );
+    let ppuSL = cpu.bus.ppu.scanline;
+    let ppucyc = cpu.bus.ppu.cycles;
+    let buscyc = cpu.bus.cycles;
+    let ppuinfo = format!("PPU:{:3},{:3} CYC:{}", ppuSL, ppucyc, buscyc);
+    let mut hex_dump = vec![];
+    hex_dump.push(opscode);
+
+    let (mem_addr, stored_value) = match opscode_data.mode {
+        AddressingMode::Immediate | AddressingMode::NotSupported => (0, 0),
+        _ => {
+            let addr =
+                cpu.get_operand_addressing_mode_trace(&opscode_data.mode, program_counter + 1);
+            (addr, cpu.mem_read(addr))
+        }
+    };
+
+    let tmp = match opscode_data.len {
+        1 => match opscode_data.code {
+            0x0a | 0x4a | 0x2a | 0x6a => format!("A "),
+            _ => String::from(""),
+        },
+        2 => {
+            let address: u8 = cpu.mem_read(program_counter + 1);
+            // let value = cpu.mem_read(address));
+            hex_dump.push(address);
+
+            match opscode_data.mode {
+                AddressingMode::Immediate => format!("#${:02x}", address),
+                AddressingMode::ZeroPage => format!("${:02x} = {:02x}", mem_addr, stored_value),
+                AddressingMode::ZeroPage_X => format!(
+                    "${:02x},X @ {:02x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                AddressingMode::ZeroPage_Y => format!(
+                    "${:02x},Y @ {:02x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                AddressingMode::Indirect_X => format!(
+                    "(${:02x},X) @ {:02x} = {:04x} = {:02x}",
+                    address,
+                    (address.wrapping_add(cpu.register_x)),
+                    mem_addr,
+                    stored_value
+                ),
+                AddressingMode::Indirect_Y => format!(
+                    "(${:02x}),Y = {:04x} @ {:04x} = {:02x}",
+                    address,
+                    (mem_addr.wrapping_sub(cpu.register_y as u16)),
+                    mem_addr,
+                    stored_value
+                ),
+                AddressingMode::NotSupported => {
+                    // assuming local jumps: BNE, BVS, etc....
+                    let address: usize =
+                        (program_counter as usize + 2).wrapping_add((address as i8) as usize);
+                    format!("${:04x}", address)
+                }
+
+                _ => panic!(
+                    "unexpected addressing mode {:?} has ops-len 2. code {:02x}",
+                    opscode_data.mode, opscode_data.code
+                ),
+            }
+        }
+        3 => {
+            let address_lo = cpu.mem_read(program_counter + 1);
+            let address_hi = cpu.mem_read(program_counter + 2);
+            hex_dump.push(address_lo);
+            hex_dump.push(address_hi);
+
+            let address = cpu.mem_read_u16(program_counter + 1);
+
+            match opscode_data.mode {
+                AddressingMode::NotSupported => {
+                    if opscode_data.code == 0x6c {
+                        //jmp indirect
+                        let jmp_addr = if address & 0x00FF == 0x00FF {
+                            let lo = cpu.mem_read(address);
+                            let hi = cpu.mem_read(address & 0xFF00);
+                            (hi as u16) << 8 | (lo as u16)
+                        } else {
+                            cpu.mem_read_u16(address)
+                        };
+
+                        // let jmp_addr = cpu.mem_read_u16(address);
+                        format!("(${:04x}) = {:04x}", address, jmp_addr)
+                    } else {
+                        format!("${:04x}", address)
+                    }
+                }
+                AddressingMode::Absolute => format!("${:04x} = {:02x}", mem_addr, stored_value),
+                AddressingMode::Absolute_X => format!(
+                    "${:04x},X @ {:04x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                AddressingMode::Absolute_Y => format!(
+                    "${:04x},Y @ {:04x} = {:02x}",
+                    address, mem_addr, stored_value
+                ),
+                _ => panic!(
+                    "unexpected addressing mode {:?} has ops-len 3. code {:02x}",
+                    opscode_data.mode, opscode_data.code
+                ),
+            }
+        }
+        _ => String::from(""),
+    };
+
+    let hex_str = hex_dump
+        .iter()
+        .map(|z| format!("{:02x}", z))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let asm_str = format!(
+        "{:04x}  {:8} {: >4} {}",
+        program_counter, hex_str, opscode_data.name, tmp
+    )
+    .trim()
+    .to_string();
+    format!("{:47} {} {}", asm_str, registers, ppuinfo).to_ascii_uppercase()
+}